@@ -0,0 +1,101 @@
+use bevy::prelude::*;
+use lightyear::prelude::client::*;
+
+use crate::protocol::{Cosmetic, LocalPlayer, PlayerId, PlayerProfile};
+use crate::world::GameAssets;
+
+/// Toggles the debug gizmos.
+const DEBUG_GIZMOS_KEY: KeyCode = KeyCode::F3;
+
+/// Gives every player on the client a mesh in its profile's color and cosmetic, and draws the
+/// debug gizmos when they're turned on.
+pub struct AppearancePlugin;
+
+impl Plugin for AppearancePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DebugGizmos>().add_systems(
+            Update,
+            (
+                add_player_visuals.run_if(resource_exists::<GameAssets>),
+                toggle_debug_gizmos,
+                (draw_interpolated_boxes, draw_confirmed_boxes).run_if(debug_gizmos_enabled),
+            ),
+        );
+    }
+}
+
+/// Wireframes of the interpolated/predicted (blue) and confirmed (red) players, off by default.
+#[derive(Resource, Default)]
+pub struct DebugGizmos(pub bool);
+
+fn debug_gizmos_enabled(debug_gizmos: Res<DebugGizmos>) -> bool {
+    debug_gizmos.0
+}
+
+fn toggle_debug_gizmos(keys: Res<ButtonInput<KeyCode>>, mut debug_gizmos: ResMut<DebugGizmos>) {
+    if keys.just_pressed(DEBUG_GIZMOS_KEY) {
+        debug_gizmos.0 = !debug_gizmos.0;
+        info!("Debug gizmos: {}", debug_gizmos.0);
+    }
+}
+
+/// Both the local predicted player and remote interpolated ones arrive without anything to
+/// render. Their `Transform` is replicated, so it is left alone.
+fn add_player_visuals(
+    players: Query<
+        (Entity, Option<&PlayerProfile>),
+        (With<PlayerId>, Or<(Added<Predicted>, Added<Interpolated>)>),
+    >,
+    game_assets: Res<GameAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
+    for (entity, profile) in players.iter() {
+        let material = match profile {
+            Some(profile) => materials.add(profile.color),
+            None => game_assets.player_material.clone(),
+        };
+
+        let cosmetic = match profile.map(|profile| profile.cosmetic) {
+            Some(Cosmetic::Hat) => Some((game_assets.hat_mesh.clone(), Vec3::new(0.0, 0.6, 0.0))),
+            Some(Cosmetic::Antenna) => {
+                Some((game_assets.antenna_mesh.clone(), Vec3::new(0.3, 0.9, 0.3)))
+            }
+            Some(Cosmetic::None) | None => None,
+        };
+
+        commands
+            .entity(entity)
+            .insert((
+                game_assets.player_mesh.clone(),
+                material.clone(),
+                GlobalTransform::default(),
+                VisibilityBundle::default(),
+            ))
+            .with_children(|parent| {
+                if let Some((mesh, offset)) = cosmetic {
+                    parent.spawn(PbrBundle {
+                        mesh,
+                        material,
+                        transform: Transform::from_translation(offset),
+                        ..default()
+                    });
+                }
+            });
+    }
+}
+
+fn draw_interpolated_boxes(
+    mut gizmos: Gizmos,
+    players: Query<&Transform, Or<(With<Interpolated>, With<LocalPlayer>)>>,
+) {
+    for position in &players {
+        gizmos.cuboid(position.with_scale(Vec3::splat(1.0)), Color::BLUE);
+    }
+}
+
+fn draw_confirmed_boxes(mut gizmos: Gizmos, players: Query<&Transform, With<Confirmed>>) {
+    for position in &players {
+        gizmos.cuboid(position.with_scale(Vec3::splat(1.0)), Color::RED);
+    }
+}
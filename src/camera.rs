@@ -0,0 +1,186 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use bevy_xpbd_3d::prelude::*;
+use lightyear::prelude::client::*;
+
+use crate::client::PlayerCamera;
+use crate::collision::GameLayer;
+use crate::controls::Controls;
+use crate::protocol::LocalPlayer;
+
+/// The camera jumps instead of smoothing when the player moved further than this in a frame.
+const SNAP_DISTANCE: f32 = 10.0;
+
+/// How the follow camera behaves, tweakable at runtime.
+#[derive(Resource, Clone, Debug)]
+pub struct FollowCameraSettings {
+    /// Point the camera looks at, relative to the player.
+    pub target_offset: Vec3,
+    /// Distance from the target when nothing is in the way.
+    pub distance: f32,
+    /// How quickly the camera catches up with the player, higher is snappier.
+    pub smoothing: f32,
+    /// Radians per pixel of mouse movement, while the right mouse button is held.
+    pub mouse_sensitivity: f32,
+    /// Radians per second with the camera stick fully pushed, the one `Controls` doesn't use for
+    /// moving.
+    pub stick_sensitivity: f32,
+    /// Limits of the pitch, in radians, negative looks down.
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    /// Space kept between the camera and geometry it collides with.
+    pub collision_margin: f32,
+}
+
+impl Default for FollowCameraSettings {
+    fn default() -> Self {
+        Self {
+            target_offset: Vec3::new(0.0, 1.0, 0.0),
+            distance: 8.0,
+            smoothing: 12.0,
+            mouse_sensitivity: 0.005,
+            stick_sensitivity: 2.5,
+            min_pitch: -1.4,
+            max_pitch: 0.3,
+            collision_margin: 0.2,
+        }
+    }
+}
+
+/// A camera orbiting around a player, the `LocalPlayer` unless `target` says otherwise.
+#[derive(Component, Debug)]
+pub struct FollowCamera {
+    pub target: Option<Entity>,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Smoothed position of the look-at point.
+    smoothed_target: Option<Vec3>,
+}
+
+impl FollowCamera {
+    pub fn following(target: Entity) -> Self {
+        Self {
+            target: Some(target),
+            ..default()
+        }
+    }
+}
+
+impl Default for FollowCamera {
+    fn default() -> Self {
+        Self {
+            target: None,
+            yaw: 0.0,
+            pitch: -0.6,
+            smoothed_target: None,
+        }
+    }
+}
+
+/// Keeps each `FollowCamera` behind the player it follows.
+pub struct FollowCameraPlugin;
+
+impl Plugin for FollowCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FollowCameraSettings>()
+            .add_systems(Update, orbit_camera)
+            .add_systems(
+                PostUpdate,
+                // after lightyear applies the visual correction to the predicted player
+                follow_players
+                    .after(PredictionSet::VisualCorrection)
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+fn orbit_camera(
+    time: Res<Time>,
+    settings: Res<FollowCameraSettings>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    controls: Res<Controls>,
+    mut cameras: Query<&mut FollowCamera>,
+) {
+    let mut delta = Vec2::ZERO;
+    if mouse_buttons.pressed(MouseButton::Right) {
+        delta += mouse_motion.read().map(|motion| motion.delta).sum::<Vec2>()
+            * settings.mouse_sensitivity;
+    } else {
+        mouse_motion.clear();
+    }
+
+    let (stick_x, stick_y) = controls.move_stick.other().axes();
+    for gamepad in gamepads.iter() {
+        let stick = Vec2::new(
+            axes.get(GamepadAxis::new(gamepad, stick_x))
+                .unwrap_or_default(),
+            -axes
+                .get(GamepadAxis::new(gamepad, stick_y))
+                .unwrap_or_default(),
+        );
+        delta += stick * settings.stick_sensitivity * time.delta_seconds();
+    }
+
+    if delta == Vec2::ZERO {
+        return;
+    }
+    for mut camera in cameras.iter_mut() {
+        camera.yaw -= delta.x;
+        camera.pitch = (camera.pitch - delta.y).clamp(settings.min_pitch, settings.max_pitch);
+    }
+}
+
+pub(crate) fn follow_players(
+    time: Res<Time>,
+    settings: Res<FollowCameraSettings>,
+    spatial_query: SpatialQuery,
+    local_player: Query<Entity, With<LocalPlayer>>,
+    players: Query<&Transform, Without<PlayerCamera>>,
+    mut cameras: Query<(&mut FollowCamera, &mut Transform), With<PlayerCamera>>,
+) {
+    for (mut camera, mut transform) in cameras.iter_mut() {
+        let Some(player_entity) = camera.target.or_else(|| local_player.get_single().ok()) else {
+            continue;
+        };
+        let Ok(player_transform) = players.get(player_entity) else {
+            continue;
+        };
+
+        let goal = player_transform.translation + settings.target_offset;
+        // frame rate independent exponential smoothing, which also hides the fixed timestep
+        let blend = 1.0 - (-settings.smoothing * time.delta_seconds()).exp();
+        let target = match camera.smoothed_target {
+            // a respawn or teleport, don't sweep across the level
+            Some(target) if target.distance(goal) < SNAP_DISTANCE => target.lerp(goal, blend),
+            _ => goal,
+        };
+        camera.smoothed_target = Some(target);
+
+        let rotation = Quat::from_euler(EulerRot::YXZ, camera.yaw, camera.pitch, 0.0);
+        let back = rotation * Vec3::Z;
+
+        // pull the camera in front of whatever is between it and the player
+        let distance = Direction3d::new(back)
+            .ok()
+            .and_then(|direction| {
+                spatial_query.cast_ray(
+                    target,
+                    direction,
+                    settings.distance,
+                    true,
+                    // only the level, other players walking past shouldn't pull the camera in
+                    SpatialQueryFilter::from_mask(GameLayer::Level),
+                )
+            })
+            .map_or(settings.distance, |hit| {
+                (hit.time_of_impact - settings.collision_margin).max(0.0)
+            });
+
+        transform.translation = target + back * distance;
+        transform.rotation = rotation;
+    }
+}
@@ -7,7 +7,7 @@ use bevy_xpbd_3d::{
     components::{ColliderDensity, LockedAxes, RigidBody},
     plugins::collision::Collider,
 };
-use leafwing_input_manager::{action_state::ActionState, input_map::InputMap, InputManagerBundle};
+use leafwing_input_manager::{action_state::ActionState, InputManagerBundle};
 
 use std::net::{Ipv4Addr, SocketAddr};
 
@@ -17,26 +17,49 @@ use lightyear::prelude::*;
 use lightyear::shared::config::Mode;
 
 use bevy::log::{Level, LogPlugin};
+use lightyear::client::events::MessageEvent;
 
+use crate::level::{LevelGeometry, LevelPlugin};
 use crate::shared::SharedPlugin;
 use crate::{
+    appearance::AppearancePlugin,
+    camera::{FollowCamera, FollowCameraPlugin},
+    collision::{player_layers, PlayerCollisionPlugin},
+    controls::{Controls, ControlsPlugin},
+    loading::LoadingPlugin,
+    menu::{DisconnectReason, MenuPlugin},
     movement::MovementPlugin,
+    nameplate::NameplatePlugin,
     protocol::{
-        protocol, Channel1, ClientAssetLoadingComplete, ClientConnectionManager, LocalPlayer,
-        PlayerActions, PlayerId,
+        protocol, protocol_hash, Channel1, ClientConnectionManager, ClientHello, HandshakeAccept,
+        HandshakeReject, LocalPlayer, PlayerActions, PlayerId, ServerShutdown, GAME_VERSION,
     },
+    reconnect::{handle_connection_loss, ReconnectPlugin},
+    roster::RosterPlugin,
     shared::shared_config,
-    world::setup_world,
+    spectator::SpectatorPlugin,
+    teleport::TeleportClientPlugin,
+    tuning::{MovementSettingsHistory, TuningClientPlugin},
+    world::{setup_world, LevelDownload, LevelEntity, WorldPlugin},
 };
 
 #[derive(States, Default, Clone, Eq, PartialEq, Debug, Hash)]
 pub enum GameClientState {
     #[default]
+    MainMenu,
     Loading,
     Connecting,
     Playing,
+    /// Rebinding controls, reached from the main menu.
+    Controls,
+    /// The connection dropped or never came up; we retry from here.
+    Disconnected,
 }
 
+/// The camera spawned for the local player, torn down when leaving a game.
+#[derive(Component)]
+pub struct PlayerCamera;
+
 pub fn client_app(net_config: client::NetConfig) -> App {
     let mut app = App::new();
 
@@ -71,9 +94,20 @@ pub fn client_app(net_config: client::NetConfig) -> App {
                 level: Level::INFO,
                 filter: "wgpu=error,symphonia_core=error,symphonia_format_ogg=error,lightyear::client::prediction::rollback=debug".to_string(),
             }),
-        // WorldPlugin,
+        WorldPlugin,
+        LevelPlugin { headless: false },
+        LoadingPlugin,
         WorldInspectorPlugin::new(),
         MovementPlugin,
+        MenuPlugin,
+        ControlsPlugin,
+        RosterPlugin,
+        ReconnectPlugin,
+        TeleportClientPlugin,
+        AppearancePlugin,
+        NameplatePlugin,
+        FollowCameraPlugin,
+        SpectatorPlugin,
     ));
 
     app.add_systems(OnEnter(GameClientState::Loading), setup_world);
@@ -85,11 +119,26 @@ pub fn client_app(net_config: client::NetConfig) -> App {
             .run_if(in_state(GameClientState::Connecting)),
     );
 
+    app.add_systems(
+        PreUpdate,
+        // a shutdown notice or rejection wins over the disconnection it causes, we don't want to reconnect
+        (handle_server_shutdown, handle_handshake_reject)
+            .after(MainSet::Receive)
+            .after(handle_connection_loss),
+    );
+
     app.add_systems(
         Update,
         wait_for_local_player_spawn.run_if(in_state(GameClientState::Connecting)),
     );
+    app.add_systems(
+        OnEnter(GameClientState::MainMenu),
+        (cleanup_session, cleanup_level),
+    );
+    app.add_systems(OnEnter(GameClientState::Disconnected), cleanup_session);
 
+    app.add_plugins(TuningClientPlugin);
+    app.add_plugins(PlayerCollisionPlugin);
     app.add_plugins(SharedPlugin);
 
     app
@@ -102,27 +151,98 @@ fn try_connecting(mut next_state: ResMut<NextState<NetworkingState>>) {
 fn handle_connection(
     mut client: ResMut<ClientConnectionManager>,
     mut connection_event: EventReader<ConnectEvent>,
+    mut accepted: EventReader<MessageEvent<HandshakeAccept>>,
 ) {
     for _event in connection_event.read() {
-        // let server know we've finished loading assets
         client
-            .send_message_to_target::<Channel1, ClientAssetLoadingComplete>(
-                ClientAssetLoadingComplete {},
+            .send_message_to_target::<Channel1, ClientHello>(
+                ClientHello {
+                    game_version: GAME_VERSION.to_string(),
+                    protocol_hash: protocol_hash(),
+                },
                 NetworkTarget::None,
             )
             .unwrap_or_else(|e| {
                 error!("Failed to send message: {:?}", e);
             });
     }
+
+    for _event in accepted.read() {
+        // the server sends the level right after, see `WorldPlugin`
+        info!("Server accepted us, waiting for the level");
+    }
+}
+
+fn handle_handshake_reject(
+    mut rejections: EventReader<MessageEvent<HandshakeReject>>,
+    mut reason: ResMut<DisconnectReason>,
+    mut next_network_state: ResMut<NextState<NetworkingState>>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    for event in rejections.read() {
+        let message = format!("rejected by server: {}", event.message().reason);
+        warn!("{}", message);
+
+        // retrying won't help, so go back to the menu instead of `Disconnected`
+        reason.0 = Some(message);
+        next_network_state.set(NetworkingState::Disconnected);
+        next_state.set(GameClientState::MainMenu);
+    }
+}
+
+fn handle_server_shutdown(
+    mut shutdown: EventReader<MessageEvent<ServerShutdown>>,
+    mut reason: ResMut<DisconnectReason>,
+    mut next_network_state: ResMut<NextState<NetworkingState>>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    for event in shutdown.read() {
+        let message = format!("server shut down: {}", event.message().reason);
+        info!("{}", message);
+
+        reason.0 = Some(message);
+        next_network_state.set(NetworkingState::Disconnected);
+        next_state.set(GameClientState::MainMenu);
+    }
+}
+
+/// Removes everything that belonged to the previous connection, so the next one starts clean.
+fn cleanup_session(
+    mut commands: Commands,
+    session: Query<
+        Entity,
+        Or<(
+            With<PlayerCamera>,
+            With<Predicted>,
+            With<Interpolated>,
+            With<Confirmed>,
+        )>,
+    >,
+) {
+    for entity in session.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // the next server sends its own settings and level during the handshake
+    commands.insert_resource(MovementSettingsHistory::default());
+    commands.insert_resource(LevelDownload::default());
+}
+
+/// The menu can lead to a different server, so the level is loaded again from scratch.
+fn cleanup_level(
+    mut commands: Commands,
+    level: Query<Entity, Or<(With<LevelEntity>, With<LevelGeometry>)>>,
+) {
+    for entity in level.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
 }
 
 fn wait_for_local_player_spawn(
     confirmed: Query<(Entity, &PlayerId), Added<Predicted>>,
     client_config: Res<ClientConfig>,
+    controls: Res<Controls>,
     mut next_state: ResMut<NextState<GameClientState>>,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
     for (entity, player_id) in confirmed.iter() {
         info!("Adding player: {:?}", player_id);
@@ -134,75 +254,74 @@ fn wait_for_local_player_spawn(
 
             commands
                 .entity(entity)
+                // the mesh is added by `AppearancePlugin`, like for every other player
                 .insert(LocalPlayer)
-                .insert(PbrBundle {
-                    mesh: meshes.add(Cuboid::new(1.0, 1.0, 1.0)),
-                    material: materials.add(Color::rgb(0.8, 0.7, 0.6)),
-                    transform: Transform::from_xyz(0.0, 0.5, 0.0),
-                    ..default()
-                })
                 .insert(InputManagerBundle::<PlayerActions> {
                     action_state: ActionState::default(),
-                    input_map: InputMap::new([
-                        (PlayerActions::Up, KeyCode::KeyW),
-                        (PlayerActions::Down, KeyCode::KeyS),
-                        (PlayerActions::Left, KeyCode::KeyA),
-                        (PlayerActions::Right, KeyCode::KeyD),
-                        (PlayerActions::Jump, KeyCode::Space),
-                    ]),
+                    input_map: controls.input_map(),
                 })
                 .insert((
                     Collider::cuboid(1.0, 1.0, 1.0),
                     ColliderDensity(3.0),
+                    player_layers(),
                     RigidBody::Dynamic,
                     LockedAxes::ROTATION_LOCKED,
                     TnuaControllerBundle::default(),
                     TnuaXpbd3dSensorShape(Collider::cuboid(0.98, 0.98, 0.98)),
                 ));
 
-            // TODO move this somewhere else ?
-            commands.spawn((
-                Camera3dBundle {
-                    camera: Camera {
-                        hdr: true,
-                        ..default()
-                    },
-                    tonemapping: Tonemapping::ReinhardLuminance,
-                    color_grading: ColorGrading {
-                        exposure: 0.0,
-                        gamma: 1.07,
-                        pre_saturation: 1.0,
-                        post_saturation: 1.1,
-                        ..default()
-                    },
-                    projection: PerspectiveProjection {
-                        fov: (75.0_f32).to_radians(),
-                        // near: 0.11,
-                        // far: 1000.0,
-                        aspect_ratio: 1920.0 / 1080.0,
-                        ..default()
-                    }
-                    .into(),
-                    transform: Transform::from_xyz(0.0, 4.8, 5.0)
-                        .with_rotation(Quat::from_xyzw(-0.5, 0.0, 0.0, 1.0)),
-                    ..default()
-                },
-                FogSettings {
-                    color: Color::rgba(0.29, 0.41, 0.50, 0.5),
-                    directional_light_color: Color::rgba_u8(255, 238, 227, 127),
-                    directional_light_exponent: 30.0,
-                    falloff: FogFalloff::Linear {
-                        start: 100.0,
-                        end: 1000.0,
-                    },
-                },
-            ));
+            commands
+                .entity(spawn_player_camera(&mut commands))
+                .insert(FollowCamera::default());
 
             next_state.set(GameClientState::Playing);
         }
     }
 }
 
+/// The 3D camera used while in game, for players and spectators alike.
+pub(crate) fn spawn_player_camera(commands: &mut Commands) -> Entity {
+    commands
+        .spawn((
+            Camera3dBundle {
+                camera: Camera {
+                    hdr: true,
+                    ..default()
+                },
+                tonemapping: Tonemapping::ReinhardLuminance,
+                color_grading: ColorGrading {
+                    exposure: 0.0,
+                    gamma: 1.07,
+                    pre_saturation: 1.0,
+                    post_saturation: 1.1,
+                    ..default()
+                },
+                projection: PerspectiveProjection {
+                    fov: (75.0_f32).to_radians(),
+                    // near: 0.11,
+                    // far: 1000.0,
+                    aspect_ratio: 1920.0 / 1080.0,
+                    ..default()
+                }
+                .into(),
+                transform: Transform::from_xyz(0.0, 4.8, 5.0)
+                    .with_rotation(Quat::from_xyzw(-0.5, 0.0, 0.0, 1.0)),
+                ..default()
+            },
+            FogSettings {
+                color: Color::rgba(0.29, 0.41, 0.50, 0.5),
+                directional_light_color: Color::rgba_u8(255, 238, 227, 127),
+                directional_light_exponent: 30.0,
+                falloff: FogFalloff::Linear {
+                    start: 100.0,
+                    end: 1000.0,
+                },
+            },
+            PlayerCamera,
+        ))
+        .id()
+}
+
 pub fn build_client_net_config(
     client_id: u64,
     server_details: &str,
@@ -232,7 +351,28 @@ pub fn build_client_net_config(
     };
 }
 
-fn get_local_client_id(net_config: &NetConfig) -> Option<u64> {
+pub(crate) fn get_server_addr(net_config: &NetConfig) -> Option<SocketAddr> {
+    if let NetConfig::Netcode {
+        auth,
+        config: _,
+        io: _,
+    } = net_config
+    {
+        if let Authentication::Manual {
+            server_addr,
+            client_id: _,
+            private_key: _,
+            protocol_id: _,
+        } = auth
+        {
+            return Some(*server_addr);
+        }
+    }
+
+    None
+}
+
+pub(crate) fn get_local_client_id(net_config: &NetConfig) -> Option<u64> {
     if let NetConfig::Netcode {
         auth,
         config: _,
@@ -0,0 +1,131 @@
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use lightyear::client::interpolation::InterpolateStatus;
+use lightyear::prelude::client::{Interpolated, Rollback};
+use lightyear::prelude::*;
+
+use crate::protocol::{PlayerBundle, PlayerId};
+use crate::tuning::simulated_tick;
+
+/// Proxies jump instead of sweeping when their player moved further than this in a tick.
+const SNAP_DISTANCE: f32 = 5.0;
+/// Proxies are extrapolated at most this many ticks away from their interpolated player, so a
+/// long stall doesn't throw them across the level.
+const MAX_LEAD_TICKS: i16 = 32;
+
+/// What each collider is. The server and the client give the same colliders the same layers,
+/// so the client predicts the collisions the server simulates.
+#[derive(PhysicsLayer, Clone, Copy, Debug)]
+pub enum GameLayer {
+    Level,
+    Player,
+}
+
+/// Level geometry blocks everything.
+pub(crate) fn level_layers() -> CollisionLayers {
+    CollisionLayers::new([GameLayer::Level], LayerMask::ALL)
+}
+
+/// Players collide with the level and with each other.
+pub(crate) fn player_layers() -> CollisionLayers {
+    CollisionLayers::new([GameLayer::Player], [GameLayer::Level, GameLayer::Player])
+}
+
+/// Lets the local player bump into remote players.
+///
+/// Remote players are interpolated and have no physics on the client. Each one gets a
+/// kinematic proxy following it, which the predicted local player collides with. The proxy
+/// can't be pushed, so the client never predicts moving someone it doesn't control; the server,
+/// where all players are dynamic, does the actual pushing.
+///
+/// Remote players are drawn in the past while the local player is predicted ahead of the
+/// server, so a proxy placed where its player is drawn would be hit somewhere the server never
+/// had it, and every bump would end in a rollback. Proxies are extrapolated to the tick being
+/// simulated instead, the predicted one or the one a rollback is replaying, along the velocity
+/// between the two server updates being interpolated.
+pub struct PlayerCollisionPlugin;
+
+impl Plugin for PlayerCollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (spawn_player_proxies, despawn_player_proxies))
+            .add_systems(
+                FixedUpdate,
+                move_player_proxies.before(PhysicsSet::StepSimulation),
+            );
+    }
+}
+
+/// Kinematic stand-in for the remote player `target`.
+#[derive(Component)]
+struct PlayerProxy {
+    target: Entity,
+}
+
+fn spawn_player_proxies(
+    players: Query<(Entity, &Transform), (With<PlayerId>, Added<Interpolated>)>,
+    mut commands: Commands,
+) {
+    for (entity, transform) in players.iter() {
+        commands.spawn((
+            PlayerProxy { target: entity },
+            RigidBody::Kinematic,
+            PlayerBundle::collider(),
+            player_layers(),
+            TransformBundle::from_transform(Transform::from_translation(transform.translation)),
+        ));
+    }
+}
+
+fn despawn_player_proxies(
+    proxies: Query<(Entity, &PlayerProxy)>,
+    players: Query<(), With<Interpolated>>,
+    mut commands: Commands,
+) {
+    for (entity, proxy) in proxies.iter() {
+        if !players.contains(proxy.target) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Moves each proxy to where its player should be at the simulated tick through its velocity
+/// rather than its position, so the local player is pushed out of the way instead of ending up
+/// inside it.
+fn move_player_proxies(
+    time: Res<Time>,
+    tick_manager: Res<TickManager>,
+    rollback: Option<Res<Rollback>>,
+    players: Query<
+        (&Transform, &InterpolateStatus<Transform>),
+        (With<Interpolated>, Without<PlayerProxy>),
+    >,
+    mut proxies: Query<(&PlayerProxy, &mut Position, &mut LinearVelocity)>,
+) {
+    let tick_duration = time.delta_seconds();
+    // while resimulating a rollback, where the players were at the tick being replayed
+    let tick = simulated_tick(&tick_manager, rollback.as_deref());
+    for (proxy, mut position, mut velocity) in proxies.iter_mut() {
+        let Ok((target, status)) = players.get(proxy.target) else {
+            continue;
+        };
+
+        let target_velocity = match (&status.start, &status.end) {
+            (Some((start_tick, start)), Some((end_tick, end))) if end_tick > start_tick => {
+                (end.translation - start.translation)
+                    / ((*end_tick - *start_tick) as f32 * tick_duration)
+            }
+            _ => Vec3::ZERO,
+        };
+        let lead = (tick - status.current).clamp(-MAX_LEAD_TICKS, MAX_LEAD_TICKS);
+        let predicted = target.translation + target_velocity * lead as f32 * tick_duration;
+
+        let offset = predicted - position.0;
+        // a respawn or teleport
+        if offset.length() > SNAP_DISTANCE {
+            position.0 = predicted;
+            *velocity = LinearVelocity::ZERO;
+        } else {
+            velocity.0 = offset / tick_duration;
+        }
+    }
+}
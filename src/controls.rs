@@ -0,0 +1,612 @@
+use bevy::prelude::*;
+use leafwing_input_manager::axislike::{DualAxis, VirtualDPad};
+use leafwing_input_manager::input_map::InputMap;
+use leafwing_input_manager::user_input::InputKind;
+use serde::{Deserialize, Serialize};
+
+use crate::client::GameClientState;
+use crate::menu::{button_bundle, BUTTON_COLOR, ERROR_COLOR, HOVERED_BUTTON_COLOR};
+use crate::protocol::PlayerActions;
+use crate::settings::{self, CONTROLS_SETTINGS_FILE};
+
+/// Color of the binding waiting for an input.
+const CAPTURING_BUTTON_COLOR: Color = Color::rgb(0.5, 0.45, 0.2);
+
+/// Keys the game already uses for something else, which can't be bound.
+const RESERVED_KEYS: [(KeyCode, &str); 2] = [
+    (KeyCode::Escape, "leaving screens"),
+    (KeyCode::F3, "the debug gizmos"),
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stick {
+    Left,
+    Right,
+}
+
+impl Stick {
+    /// The camera orbits with whichever stick doesn't move the player.
+    pub(crate) fn other(self) -> Self {
+        match self {
+            Stick::Left => Stick::Right,
+            Stick::Right => Stick::Left,
+        }
+    }
+
+    pub(crate) fn axes(self) -> (GamepadAxisType, GamepadAxisType) {
+        match self {
+            Stick::Left => (GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY),
+            Stick::Right => (GamepadAxisType::RightStickX, GamepadAxisType::RightStickY),
+        }
+    }
+}
+
+/// The player's bindings for each `PlayerActions` entry. `CameraYaw` follows the camera, so it
+/// has none.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub(crate) struct Controls {
+    pub(crate) move_forward: KeyCode,
+    pub(crate) move_back: KeyCode,
+    pub(crate) move_left: KeyCode,
+    pub(crate) move_right: KeyCode,
+    pub(crate) move_stick: Stick,
+    pub(crate) jump_key: KeyCode,
+    pub(crate) jump_button: GamepadButtonType,
+    pub(crate) sprint_key: KeyCode,
+    pub(crate) sprint_button: GamepadButtonType,
+    pub(crate) crouch_key: KeyCode,
+    pub(crate) crouch_button: GamepadButtonType,
+    pub(crate) dash_key: KeyCode,
+    pub(crate) dash_button: GamepadButtonType,
+}
+
+/// WASD and the left stick move, Space and the south button jump, Shift and the left stick
+/// button sprint, Ctrl and the east button crouch, Q and the west button dash.
+impl Default for Controls {
+    fn default() -> Self {
+        Self {
+            move_forward: KeyCode::KeyW,
+            move_back: KeyCode::KeyS,
+            move_left: KeyCode::KeyA,
+            move_right: KeyCode::KeyD,
+            move_stick: Stick::Left,
+            jump_key: KeyCode::Space,
+            jump_button: GamepadButtonType::South,
+            sprint_key: KeyCode::ShiftLeft,
+            sprint_button: GamepadButtonType::LeftThumb,
+            crouch_key: KeyCode::ControlLeft,
+            crouch_button: GamepadButtonType::East,
+            dash_key: KeyCode::KeyQ,
+            dash_button: GamepadButtonType::West,
+        }
+    }
+}
+
+impl Controls {
+    /// Loads the saved controls, falling back to the defaults if they conflict.
+    fn load() -> Self {
+        let controls = settings::load::<Controls>(CONTROLS_SETTINGS_FILE);
+        let conflicts = controls.conflicts();
+        if conflicts.is_empty() {
+            controls
+        } else {
+            warn!("Ignoring saved controls: {}", conflicts.join(", "));
+            Controls::default()
+        }
+    }
+
+    pub(crate) fn input_map(&self) -> InputMap<PlayerActions> {
+        let dpad = VirtualDPad {
+            up: InputKind::PhysicalKey(self.move_forward),
+            down: InputKind::PhysicalKey(self.move_back),
+            left: InputKind::PhysicalKey(self.move_left),
+            right: InputKind::PhysicalKey(self.move_right),
+        };
+        let stick = match self.move_stick {
+            Stick::Left => DualAxis::left_stick(),
+            Stick::Right => DualAxis::right_stick(),
+        };
+
+        let mut input_map = InputMap::new([
+            (PlayerActions::Jump, self.jump_key),
+            (PlayerActions::Sprint, self.sprint_key),
+            (PlayerActions::Crouch, self.crouch_key),
+            (PlayerActions::Dash, self.dash_key),
+        ]);
+        input_map
+            .insert(PlayerActions::Move, dpad)
+            .insert(PlayerActions::Move, stick)
+            .insert(PlayerActions::Jump, self.jump_button)
+            .insert(PlayerActions::Sprint, self.sprint_button)
+            .insert(PlayerActions::Crouch, self.crouch_button)
+            .insert(PlayerActions::Dash, self.dash_button);
+        input_map
+    }
+
+    fn key(&self, binding: Binding) -> Option<KeyCode> {
+        match binding {
+            Binding::MoveForward => Some(self.move_forward),
+            Binding::MoveBack => Some(self.move_back),
+            Binding::MoveLeft => Some(self.move_left),
+            Binding::MoveRight => Some(self.move_right),
+            Binding::JumpKey => Some(self.jump_key),
+            Binding::SprintKey => Some(self.sprint_key),
+            Binding::CrouchKey => Some(self.crouch_key),
+            Binding::DashKey => Some(self.dash_key),
+            _ => None,
+        }
+    }
+
+    fn key_mut(&mut self, binding: Binding) -> Option<&mut KeyCode> {
+        match binding {
+            Binding::MoveForward => Some(&mut self.move_forward),
+            Binding::MoveBack => Some(&mut self.move_back),
+            Binding::MoveLeft => Some(&mut self.move_left),
+            Binding::MoveRight => Some(&mut self.move_right),
+            Binding::JumpKey => Some(&mut self.jump_key),
+            Binding::SprintKey => Some(&mut self.sprint_key),
+            Binding::CrouchKey => Some(&mut self.crouch_key),
+            Binding::DashKey => Some(&mut self.dash_key),
+            _ => None,
+        }
+    }
+
+    fn button(&self, binding: Binding) -> Option<GamepadButtonType> {
+        match binding {
+            Binding::JumpButton => Some(self.jump_button),
+            Binding::SprintButton => Some(self.sprint_button),
+            Binding::CrouchButton => Some(self.crouch_button),
+            Binding::DashButton => Some(self.dash_button),
+            _ => None,
+        }
+    }
+
+    fn button_mut(&mut self, binding: Binding) -> Option<&mut GamepadButtonType> {
+        match binding {
+            Binding::JumpButton => Some(&mut self.jump_button),
+            Binding::SprintButton => Some(&mut self.sprint_button),
+            Binding::CrouchButton => Some(&mut self.crouch_button),
+            Binding::DashButton => Some(&mut self.dash_button),
+            _ => None,
+        }
+    }
+
+    /// Describes every input bound to more than one thing, empty when the controls are usable.
+    pub(crate) fn conflicts(&self) -> Vec<String> {
+        let inputs = Binding::ALL
+            .iter()
+            .filter(|binding| **binding != Binding::MoveStick)
+            .map(|binding| (*binding, self.describe(*binding)))
+            .collect::<Vec<_>>();
+
+        let mut conflicts = Vec::new();
+        for (i, (binding, input)) in inputs.iter().enumerate() {
+            for (other, other_input) in &inputs[i + 1..] {
+                // a key and a button can't be the same input
+                if input == other_input && binding.is_gamepad() == other.is_gamepad() {
+                    conflicts.push(format!(
+                        "{} is bound to both {} and {}",
+                        input,
+                        binding.label(),
+                        other.label()
+                    ));
+                }
+            }
+        }
+
+        for (reserved, used_for) in RESERVED_KEYS {
+            if let Some(binding) = Binding::ALL
+                .iter()
+                .find(|binding| self.key(**binding) == Some(reserved))
+            {
+                conflicts.push(format!(
+                    "{} is used for {}, not {}",
+                    key_name(reserved),
+                    used_for,
+                    binding.label()
+                ));
+            }
+        }
+
+        conflicts
+    }
+
+    fn describe(&self, binding: Binding) -> String {
+        if binding == Binding::MoveStick {
+            return format!("{:?} stick", self.move_stick);
+        }
+        match (self.key(binding), self.button(binding)) {
+            (Some(key), _) => key_name(key),
+            (_, Some(button)) => format!("{:?}", button),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// `KeyW` reads better as `W`.
+fn key_name(key: KeyCode) -> String {
+    let name = format!("{:?}", key);
+    name.strip_prefix("Key")
+        .or_else(|| name.strip_prefix("Digit"))
+        .unwrap_or(&name)
+        .to_string()
+}
+
+/// One input on the controls screen.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+enum Binding {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    MoveStick,
+    JumpKey,
+    JumpButton,
+    SprintKey,
+    SprintButton,
+    CrouchKey,
+    CrouchButton,
+    DashKey,
+    DashButton,
+}
+
+impl Binding {
+    const ALL: [Binding; 13] = [
+        Binding::MoveForward,
+        Binding::MoveBack,
+        Binding::MoveLeft,
+        Binding::MoveRight,
+        Binding::MoveStick,
+        Binding::JumpKey,
+        Binding::JumpButton,
+        Binding::SprintKey,
+        Binding::SprintButton,
+        Binding::CrouchKey,
+        Binding::CrouchButton,
+        Binding::DashKey,
+        Binding::DashButton,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Binding::MoveForward => "move forward",
+            Binding::MoveBack => "move back",
+            Binding::MoveLeft => "move left",
+            Binding::MoveRight => "move right",
+            Binding::MoveStick => "move",
+            Binding::JumpKey | Binding::JumpButton => "jump",
+            Binding::SprintKey | Binding::SprintButton => "sprint",
+            Binding::CrouchKey | Binding::CrouchButton => "crouch",
+            Binding::DashKey | Binding::DashButton => "dash",
+        }
+    }
+
+    fn is_gamepad(&self) -> bool {
+        matches!(
+            self,
+            Binding::MoveStick
+                | Binding::JumpButton
+                | Binding::SprintButton
+                | Binding::CrouchButton
+                | Binding::DashButton
+        )
+    }
+}
+
+/// The controls being edited, only applied when saved.
+#[derive(Resource)]
+struct ControlsDraft {
+    controls: Controls,
+    /// Waiting for the next key or gamepad button press to bind it here.
+    capturing: Option<Binding>,
+}
+
+#[derive(Component)]
+struct BindingText(Binding);
+
+#[derive(Component)]
+struct ConflictText;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum ScreenButton {
+    Save,
+    Reset,
+    Back,
+}
+
+#[derive(Component)]
+struct ControlsRoot;
+
+/// Loads the saved controls, and lets players rebind them from a screen reached from the main
+/// menu.
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Controls::load())
+            .add_systems(OnEnter(GameClientState::Controls), spawn_controls_screen)
+            .add_systems(OnExit(GameClientState::Controls), despawn_controls_screen)
+            .add_systems(
+                Update,
+                (
+                    start_capture,
+                    handle_screen_buttons,
+                    capture_input,
+                    update_binding_buttons,
+                    update_conflicts,
+                )
+                    .chain()
+                    .run_if(in_state(GameClientState::Controls)),
+            );
+    }
+}
+
+fn spawn_controls_screen(mut commands: Commands, controls: Res<Controls>) {
+    commands.insert_resource(ControlsDraft {
+        controls: controls.clone(),
+        capturing: None,
+    });
+
+    commands.spawn((Camera2dBundle::default(), ControlsRoot));
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(12.0),
+                    ..default()
+                },
+                ..default()
+            },
+            ControlsRoot,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Controls",
+                TextStyle {
+                    font_size: 64.0,
+                    ..default()
+                },
+            ));
+
+            spawn_action_row(
+                parent,
+                "Move",
+                &[
+                    Binding::MoveForward,
+                    Binding::MoveLeft,
+                    Binding::MoveBack,
+                    Binding::MoveRight,
+                ],
+                Binding::MoveStick,
+            );
+            spawn_action_row(parent, "Jump", &[Binding::JumpKey], Binding::JumpButton);
+            spawn_action_row(
+                parent,
+                "Sprint",
+                &[Binding::SprintKey],
+                Binding::SprintButton,
+            );
+            spawn_action_row(
+                parent,
+                "Crouch",
+                &[Binding::CrouchKey],
+                Binding::CrouchButton,
+            );
+            spawn_action_row(parent, "Dash", &[Binding::DashKey], Binding::DashButton);
+
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 20.0,
+                        color: ERROR_COLOR,
+                        ..default()
+                    },
+                ),
+                ConflictText,
+            ));
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(12.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|row| {
+                    for (button, label) in [
+                        (ScreenButton::Save, "Save"),
+                        (ScreenButton::Reset, "Reset to defaults"),
+                        (ScreenButton::Back, "Back"),
+                    ] {
+                        row.spawn((button_bundle(), button))
+                            .with_children(|button| {
+                                button.spawn(TextBundle::from_section(label, TextStyle::default()));
+                            });
+                    }
+                });
+        });
+}
+
+/// The action's name, then its keyboard bindings, then its gamepad one.
+fn spawn_action_row(
+    parent: &mut ChildBuilder,
+    label: &str,
+    keyboard: &[Binding],
+    gamepad: Binding,
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                column_gap: Val::Px(12.0),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(
+                TextBundle::from_section(
+                    label,
+                    TextStyle {
+                        font_size: 20.0,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    width: Val::Px(160.0),
+                    ..default()
+                }),
+            );
+
+            for binding in keyboard.iter().copied().chain([gamepad]) {
+                row.spawn((button_bundle(), binding))
+                    .with_children(|button| {
+                        button.spawn((
+                            TextBundle::from_section("", TextStyle::default()),
+                            BindingText(binding),
+                        ));
+                    });
+            }
+        });
+}
+
+fn despawn_controls_screen(mut commands: Commands, screen: Query<Entity, With<ControlsRoot>>) {
+    commands.remove_resource::<ControlsDraft>();
+    for entity in screen.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn start_capture(
+    mut draft: ResMut<ControlsDraft>,
+    buttons: Query<(&Interaction, &Binding), Changed<Interaction>>,
+) {
+    for (interaction, binding) in buttons.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        // there are only two sticks, so just flip between them, the camera takes the other one
+        if *binding == Binding::MoveStick {
+            draft.capturing = None;
+            draft.controls.move_stick = draft.controls.move_stick.other();
+        } else {
+            draft.capturing = Some(*binding);
+        }
+    }
+}
+
+fn handle_screen_buttons(
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Query<(&Interaction, &ScreenButton), Changed<Interaction>>,
+    mut draft: ResMut<ControlsDraft>,
+    mut controls: ResMut<Controls>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    let mut pressed = buttons
+        .iter()
+        .filter(|(interaction, _)| **interaction == Interaction::Pressed)
+        .map(|(_, button)| *button)
+        .collect::<Vec<_>>();
+    // Escape cancels the capture instead, see `capture_input`
+    if draft.capturing.is_none() && keys.just_pressed(KeyCode::Escape) {
+        pressed.push(ScreenButton::Back);
+    }
+
+    for button in pressed {
+        match button {
+            ScreenButton::Save => {
+                // the conflicts are already shown, see `update_conflicts`
+                if !draft.controls.conflicts().is_empty() {
+                    continue;
+                }
+                info!("Saving controls");
+                *controls = draft.controls.clone();
+                settings::save(CONTROLS_SETTINGS_FILE, &*controls);
+                next_state.set(GameClientState::MainMenu);
+            }
+            ScreenButton::Reset => {
+                draft.controls = Controls::default();
+                draft.capturing = None;
+            }
+            ScreenButton::Back => next_state.set(GameClientState::MainMenu),
+        }
+    }
+}
+
+/// Binds the next key, or gamepad button, pressed while a binding is waiting for one.
+fn capture_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut draft: ResMut<ControlsDraft>,
+) {
+    let Some(binding) = draft.capturing else {
+        return;
+    };
+
+    if keys.just_pressed(KeyCode::Escape) {
+        draft.capturing = None;
+        return;
+    }
+
+    if let Some(key) = keys.get_just_pressed().next().copied() {
+        if let Some(bound) = draft.controls.key_mut(binding) {
+            *bound = key;
+            draft.capturing = None;
+        }
+    }
+
+    if let Some(button) = gamepad_buttons.get_just_pressed().next().copied() {
+        if let Some(bound) = draft.controls.button_mut(binding) {
+            *bound = button.button_type;
+            draft.capturing = None;
+        }
+    }
+}
+
+fn update_binding_buttons(
+    draft: Res<ControlsDraft>,
+    mut texts: Query<(&mut Text, &BindingText)>,
+    mut buttons: Query<
+        (&Interaction, &mut BackgroundColor, Option<&Binding>),
+        Or<(With<Binding>, With<ScreenButton>)>,
+    >,
+) {
+    for (mut text, binding) in texts.iter_mut() {
+        text.sections[0].value = match draft.capturing {
+            Some(capturing) if capturing == binding.0 && capturing.is_gamepad() => {
+                "press a button...".to_string()
+            }
+            Some(capturing) if capturing == binding.0 => "press a key...".to_string(),
+            _ => draft.controls.describe(binding.0),
+        };
+    }
+
+    for (interaction, mut background, binding) in buttons.iter_mut() {
+        *background = if binding.is_some() && binding.copied() == draft.capturing {
+            CAPTURING_BUTTON_COLOR.into()
+        } else {
+            match interaction {
+                Interaction::Hovered | Interaction::Pressed => HOVERED_BUTTON_COLOR.into(),
+                Interaction::None => BUTTON_COLOR.into(),
+            }
+        };
+    }
+}
+
+fn update_conflicts(draft: Res<ControlsDraft>, mut text: Query<&mut Text, With<ConflictText>>) {
+    if !draft.is_changed() {
+        return;
+    }
+    for mut text in text.iter_mut() {
+        text.sections[0].value = draft.controls.conflicts().join("\n");
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use lightyear::prelude::server::{ConnectEvent, DisconnectEvent, ServerConnections};
+use lightyear::prelude::*;
+use lightyear::server::events::MessageEvent;
+
+use crate::level::LevelDescription;
+use crate::protocol::{
+    protocol_hash, Channel1, ClientAssetsReady, ClientHello, HandshakeAccept, HandshakeReject,
+    LevelChannel, LevelChunk, MovementSettingsUpdate, PlayerId, PlayerProfile,
+    ServerConnectionManager, GAME_VERSION,
+};
+use crate::tuning::{live_movement_settings, MovementSettingsHistory};
+
+/// How long a freshly connected client has to send its `ClientHello`.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a client has to download and build the level after being accepted.
+pub(crate) const ASSETS_READY_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long a rejected client stays connected, so the `HandshakeReject` reaches it.
+const REJECT_FLUSH_DELAY: Duration = Duration::from_millis(250);
+
+/// Longest display name, in characters, before a duplicate suffix is added.
+const MAX_NAME_LENGTH: usize = 16;
+/// Given to players who didn't pick a name.
+const DEFAULT_NAME: &str = "Player";
+
+/// Walks every new connection through the handshake:
+/// hello, accept or reject, level transfer, assets ready, then `ClientReady`.
+pub struct HandshakePlugin;
+
+impl Plugin for HandshakePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Handshakes>()
+            .add_event::<ClientReady>()
+            .add_systems(
+                Update,
+                (
+                    track_connections,
+                    receive_hello,
+                    receive_assets_ready,
+                    tick_handshakes,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// The client finished the handshake and should get its player.
+#[derive(Event, Clone, Debug)]
+pub struct ClientReady {
+    pub client_id: ClientId,
+    /// Already sanitized, see `sanitize_profile`.
+    pub profile: PlayerProfile,
+    /// Spectators get no player.
+    pub spectator: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HandshakeStage {
+    AwaitingHello,
+    AwaitingAssetsReady,
+    Rejected,
+}
+
+#[derive(Debug)]
+struct ClientHandshake {
+    stage: HandshakeStage,
+    /// Time left in the current stage.
+    timer: Timer,
+}
+
+impl ClientHandshake {
+    fn enter(&mut self, stage: HandshakeStage, timeout: Duration) {
+        self.stage = stage;
+        self.timer = Timer::new(timeout, TimerMode::Once);
+    }
+}
+
+/// Clients that are connected but not in game yet.
+#[derive(Resource, Default)]
+struct Handshakes(HashMap<ClientId, ClientHandshake>);
+
+fn reject(
+    server: &mut ServerConnectionManager,
+    handshake: &mut ClientHandshake,
+    client_id: ClientId,
+    reason: String,
+) {
+    warn!("Rejecting client {:?}: {}", client_id, reason);
+
+    server
+        .send_message_to_target::<Channel1, HandshakeReject>(
+            HandshakeReject { reason },
+            NetworkTarget::Single(client_id),
+        )
+        .unwrap_or_else(|e| {
+            error!("Failed to send message: {:?}", e);
+        });
+
+    handshake.enter(HandshakeStage::Rejected, REJECT_FLUSH_DELAY);
+}
+
+/// Makes a client supplied profile fit to show to everyone: a trimmed, bounded name that nobody
+/// else uses, and an opaque color.
+fn sanitize_profile(profile: &PlayerProfile, taken_names: &[String]) -> PlayerProfile {
+    let name = profile
+        .name
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    let name = name
+        .trim()
+        .chars()
+        .take(MAX_NAME_LENGTH)
+        .collect::<String>()
+        .trim_end()
+        .to_string();
+    let name = if name.is_empty() {
+        DEFAULT_NAME.to_string()
+    } else {
+        name
+    };
+
+    PlayerProfile {
+        name: unique_name(name, taken_names),
+        color: profile.color.with_a(1.0),
+        cosmetic: profile.cosmetic,
+    }
+}
+
+/// `name`, or `name (2)`, `name (3)`... whichever isn't taken yet, ignoring case.
+fn unique_name(name: String, taken_names: &[String]) -> String {
+    let is_taken = |candidate: &str| {
+        taken_names
+            .iter()
+            .any(|taken| taken.to_lowercase() == candidate.to_lowercase())
+    };
+    if !is_taken(&name) {
+        return name;
+    }
+
+    (2..)
+        .map(|n| format!("{} ({})", name, n))
+        .find(|candidate| !is_taken(candidate))
+        .expect("ran out of duplicate suffixes")
+}
+
+fn track_connections(
+    mut connections: EventReader<ConnectEvent>,
+    mut disconnections: EventReader<DisconnectEvent>,
+    mut handshakes: ResMut<Handshakes>,
+) {
+    for connection in connections.read() {
+        handshakes.0.insert(
+            *connection.context(),
+            ClientHandshake {
+                stage: HandshakeStage::AwaitingHello,
+                timer: Timer::new(HELLO_TIMEOUT, TimerMode::Once),
+            },
+        );
+    }
+
+    for disconnection in disconnections.read() {
+        handshakes.0.remove(disconnection.context());
+    }
+}
+
+fn receive_hello(
+    mut hellos: EventReader<MessageEvent<ClientHello>>,
+    mut handshakes: ResMut<Handshakes>,
+    mut server: ResMut<ServerConnectionManager>,
+    level: Res<LevelDescription>,
+    tick_manager: Res<TickManager>,
+    movement_settings: Res<MovementSettingsHistory>,
+) {
+    for event in hellos.read() {
+        let client_id = *event.context();
+        let hello = event.message();
+        let Some(handshake) = handshakes.0.get_mut(&client_id) else {
+            continue;
+        };
+        if handshake.stage != HandshakeStage::AwaitingHello {
+            continue;
+        }
+
+        info!("Received {:?} from client {:?}", hello, client_id);
+
+        if hello.game_version != GAME_VERSION {
+            reject(
+                &mut server,
+                handshake,
+                client_id,
+                format!(
+                    "game version {} does not match server version {}",
+                    hello.game_version, GAME_VERSION
+                ),
+            );
+            continue;
+        }
+        if hello.protocol_hash != protocol_hash() {
+            reject(
+                &mut server,
+                handshake,
+                client_id,
+                "network protocol does not match the server's, please update".to_string(),
+            );
+            continue;
+        }
+
+        server
+            .send_message_to_target::<Channel1, HandshakeAccept>(
+                HandshakeAccept,
+                NetworkTarget::Single(client_id),
+            )
+            .unwrap_or_else(|e| {
+                error!("Failed to send message: {:?}", e);
+            });
+        for update in live_movement_settings(&tick_manager, &movement_settings) {
+            server
+                .send_message_to_target::<Channel1, MovementSettingsUpdate>(
+                    update,
+                    NetworkTarget::Single(client_id),
+                )
+                .unwrap_or_else(|e| {
+                    error!("Failed to send message: {:?}", e);
+                });
+        }
+
+        let chunks = level.to_chunks();
+        info!(
+            "Sending level {:?} to client {:?} in {} chunks",
+            level.name,
+            client_id,
+            chunks.len()
+        );
+        for chunk in chunks {
+            server
+                .send_message_to_target::<LevelChannel, LevelChunk>(
+                    chunk,
+                    NetworkTarget::Single(client_id),
+                )
+                .unwrap_or_else(|e| {
+                    error!("Failed to send message: {:?}", e);
+                });
+        }
+
+        handshake.enter(HandshakeStage::AwaitingAssetsReady, ASSETS_READY_TIMEOUT);
+    }
+}
+
+fn receive_assets_ready(
+    mut reports: EventReader<MessageEvent<ClientAssetsReady>>,
+    mut handshakes: ResMut<Handshakes>,
+    mut server: ResMut<ServerConnectionManager>,
+    mut ready: EventWriter<ClientReady>,
+    level: Res<LevelDescription>,
+    players: Query<(&PlayerId, &PlayerProfile)>,
+) {
+    // names handed out this frame aren't on any player yet
+    let mut taken_names = Vec::new();
+    for event in reports.read() {
+        let client_id = *event.context();
+        let Some(handshake) = handshakes.0.get_mut(&client_id) else {
+            continue;
+        };
+        if handshake.stage != HandshakeStage::AwaitingAssetsReady {
+            continue;
+        }
+
+        if Ok(event.message().content_hash) != level.content_hash() {
+            reject(
+                &mut server,
+                handshake,
+                client_id,
+                format!(
+                    "level {} does not match the server's, is the game up to date?",
+                    level.name
+                ),
+            );
+            continue;
+        }
+
+        // a reconnecting client may keep the name of the player waiting for it
+        let names_in_use = players
+            .iter()
+            .filter(|(player_id, _)| player_id.0 != client_id)
+            .map(|(_, profile)| profile.name.clone())
+            .chain(taken_names.iter().cloned())
+            .collect::<Vec<_>>();
+        let profile = sanitize_profile(&event.message().profile, &names_in_use);
+        taken_names.push(profile.name.clone());
+
+        info!(
+            "Client {:?} completed the handshake as {:?}",
+            client_id, profile.name
+        );
+        handshakes.0.remove(&client_id);
+        ready.send(ClientReady {
+            client_id,
+            profile,
+            spectator: event.message().spectator,
+        });
+    }
+}
+
+fn tick_handshakes(
+    time: Res<Time>,
+    mut handshakes: ResMut<Handshakes>,
+    mut server: ResMut<ServerConnectionManager>,
+    mut connections: ResMut<ServerConnections>,
+) {
+    for (client_id, handshake) in handshakes.0.iter_mut() {
+        if !handshake.timer.tick(time.delta()).just_finished() {
+            continue;
+        }
+
+        match handshake.stage {
+            HandshakeStage::AwaitingHello => reject(
+                &mut server,
+                handshake,
+                *client_id,
+                "timed out waiting for hello".to_string(),
+            ),
+            HandshakeStage::AwaitingAssetsReady => reject(
+                &mut server,
+                handshake,
+                *client_id,
+                "timed out loading the level".to_string(),
+            ),
+            HandshakeStage::Rejected => {
+                info!("Disconnecting rejected client {:?}", client_id);
+                connections.disconnect(*client_id).unwrap_or_else(|e| {
+                    error!("Failed to disconnect client: {:?}", e);
+                });
+            }
+        }
+    }
+}
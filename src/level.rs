@@ -0,0 +1,262 @@
+use bevy::asset::io::file::FileAssetReader;
+use bevy::gltf::GltfExtras;
+use bevy::prelude::*;
+use bevy::scene::SceneInstanceReady;
+use bevy_xpbd_3d::{components::RigidBody, plugins::collision::Collider};
+use serde::{Deserialize, Serialize};
+
+use crate::collision::level_layers;
+use crate::protocol::{content_hash, LevelChunk};
+
+/// Payload size of a single `LevelChunk`.
+pub const LEVEL_CHUNK_SIZE: usize = 1024;
+
+/// Where `AssetPlugin` reads assets from by default.
+const ASSET_FOLDER: &str = "assets";
+
+/// Asset folder holding the level glTF files.
+pub const LEVELS_FOLDER: &str = "levels";
+
+/// Nodes whose name ends with this get a trimesh collider built from their mesh.
+const COLLIDER_NAME_SUFFIX: &str = "_collider";
+
+/// Which level to play, sent by the server after connecting. Both apps spawn the same scene
+/// file from it, so the ground and obstacles can't drift apart between client and server.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LevelDescription {
+    pub name: String,
+    /// Asset path of the glTF file holding the level.
+    pub scene: String,
+}
+
+impl Default for LevelDescription {
+    fn default() -> Self {
+        Self {
+            name: "arena".to_string(),
+            scene: "levels/arena.gltf".to_string(),
+        }
+    }
+}
+
+impl LevelDescription {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        ron::to_string(self)
+            .expect("Failed to serialize level")
+            .into_bytes()
+    }
+
+    pub fn to_chunks(&self) -> Vec<LevelChunk> {
+        let bytes = self.to_bytes();
+        let total = bytes.chunks(LEVEL_CHUNK_SIZE).len() as u32;
+
+        bytes
+            .chunks(LEVEL_CHUNK_SIZE)
+            .enumerate()
+            .map(|(index, chunk)| LevelChunk {
+                index: index as u32,
+                total,
+                bytes: chunk.to_vec(),
+            })
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+        ron::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// Hash of the description together with the glTF file it points to, as found in this
+    /// app's asset folder. Client and server only agree on it when they have the same level.
+    pub fn content_hash(&self) -> Result<u64, String> {
+        let path = FileAssetReader::get_base_path()
+            .join(ASSET_FOLDER)
+            .join(&self.scene);
+        let scene = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(content_hash(&[self.to_bytes(), scene].concat()))
+    }
+}
+
+/// Custom properties (glTF extras) understood on level nodes.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct LevelNodeProperties {
+    collider: Option<LevelCollider>,
+    /// Only matters for rendering, the server drops the node.
+    render_only: bool,
+    /// Players can be spawned at the node's position.
+    spawn_point: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum LevelCollider {
+    /// Infinite ground facing the node's local +Y.
+    Halfspace,
+    /// Box around the node's mesh.
+    Cuboid,
+    /// The node's mesh, as is.
+    Trimesh,
+}
+
+/// A place in the level where players can spawn.
+#[derive(Component, Debug)]
+pub struct SpawnPoint;
+
+/// Marks entities spawned from a `LevelDescription`.
+#[derive(Component)]
+pub struct LevelGeometry;
+
+/// Sent once the level scene is spawned and its colliders exist.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct LevelReady(pub Entity);
+
+/// Builds colliders and spawn points for freshly spawned level nodes, then sends `LevelReady`.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LevelSet;
+
+/// Turns level scenes into physics geometry. With `headless`, render-only nodes are dropped.
+pub struct LevelPlugin {
+    pub headless: bool,
+}
+
+#[derive(Resource)]
+struct LevelSettings {
+    headless: bool,
+}
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(LevelSettings {
+            headless: self.headless,
+        })
+        .add_event::<LevelReady>()
+        .add_systems(
+            Update,
+            (build_level_nodes, announce_ready_levels)
+                .chain()
+                .in_set(LevelSet),
+        );
+    }
+}
+
+/// Spawns the level scene. The root is the static rigid body all colliders attach to.
+pub fn spawn_level(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    level: &LevelDescription,
+) -> Entity {
+    info!("Spawning level {:?} from {:?}", level.name, level.scene);
+
+    commands
+        .spawn((
+            SceneBundle {
+                scene: asset_server.load(format!("{}#Scene0", level.scene)),
+                ..default()
+            },
+            RigidBody::Static,
+            LevelGeometry,
+        ))
+        .id()
+}
+
+fn node_properties(name: Option<&Name>, extras: Option<&GltfExtras>) -> LevelNodeProperties {
+    let mut properties = extras
+        .and_then(|extras| {
+            serde_json::from_str::<LevelNodeProperties>(&extras.value)
+                .map_err(|e| warn!("Ignoring level node properties {:?}: {}", extras.value, e))
+                .ok()
+        })
+        .unwrap_or_default();
+
+    if properties.collider.is_none()
+        && name.is_some_and(|name| name.as_str().ends_with(COLLIDER_NAME_SUFFIX))
+    {
+        properties.collider = Some(LevelCollider::Trimesh);
+    }
+
+    properties
+}
+
+fn build_level_nodes(
+    settings: Res<LevelSettings>,
+    nodes: Query<
+        (
+            Entity,
+            Option<&Name>,
+            Option<&GltfExtras>,
+            Option<&Children>,
+        ),
+        Or<(Added<GltfExtras>, Added<Name>)>,
+    >,
+    mesh_handles: Query<&Handle<Mesh>>,
+    meshes: Res<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    for (entity, name, extras, children) in nodes.iter() {
+        let properties = node_properties(name, extras);
+
+        if properties.render_only {
+            if settings.headless {
+                commands.entity(entity).despawn_recursive();
+            }
+            continue;
+        }
+
+        if properties.spawn_point {
+            commands.entity(entity).insert(SpawnPoint);
+        }
+
+        let Some(kind) = properties.collider else {
+            continue;
+        };
+
+        if kind == LevelCollider::Halfspace {
+            commands
+                .entity(entity)
+                .insert((Collider::halfspace(Vec3::Y), level_layers()));
+            continue;
+        }
+
+        // glTF nodes keep their mesh primitives on child entities
+        let primitives = children.into_iter().flatten().filter_map(|child| {
+            let mesh = meshes.get(mesh_handles.get(*child).ok()?)?;
+            Some((*child, mesh))
+        });
+        for (primitive, mesh) in primitives {
+            let collider = match kind {
+                LevelCollider::Cuboid => mesh.compute_aabb().map(|aabb| {
+                    let size = Vec3::from(aabb.half_extents) * 2.0;
+                    // the mesh isn't necessarily centered on its node
+                    Collider::compound(vec![(
+                        Vec3::from(aabb.center),
+                        Quat::IDENTITY,
+                        Collider::cuboid(size.x, size.y, size.z),
+                    )])
+                }),
+                _ => Collider::trimesh_from_mesh(mesh),
+            };
+
+            match collider {
+                Some(collider) => {
+                    commands
+                        .entity(primitive)
+                        .insert((collider, level_layers()));
+                }
+                None => warn!("Could not build a {:?} collider for {:?}", kind, name),
+            }
+        }
+    }
+}
+
+fn announce_ready_levels(
+    mut instances: EventReader<SceneInstanceReady>,
+    levels: Query<(), With<LevelGeometry>>,
+    mut ready: EventWriter<LevelReady>,
+) {
+    for instance in instances.read() {
+        if levels.contains(instance.parent) {
+            info!("Level {:?} is ready", instance.parent);
+            ready.send(LevelReady(instance.parent));
+        }
+    }
+}
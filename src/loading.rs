@@ -0,0 +1,161 @@
+use bevy::asset::{RecursiveDependencyLoadState, UntypedAssetId};
+use bevy::prelude::*;
+
+use crate::client::GameClientState;
+use crate::menu::DisconnectReason;
+
+const PROGRESS_BAR_WIDTH: f32 = 400.0;
+
+/// Runs `GameClientState::Loading`: waits for every asset registered in `LoadingAssets` to be
+/// loaded, with all its dependencies, before moving on to `Connecting`.
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LoadingAssets>()
+            .add_systems(OnEnter(GameClientState::Loading), spawn_loading_screen)
+            .add_systems(
+                OnExit(GameClientState::Loading),
+                (despawn_loading_screen, clear_loading_assets),
+            )
+            .add_systems(
+                Update,
+                update_loading_progress.run_if(in_state(GameClientState::Loading)),
+            );
+    }
+}
+
+/// Assets the client needs before it can connect. Systems running `OnEnter(Loading)` register
+/// what they load through the `AssetServer` here, such as glTF files and fonts. Only ids are
+/// kept, so loading systems still need to hold on to their own handles.
+#[derive(Resource, Default)]
+pub struct LoadingAssets {
+    assets: Vec<(String, UntypedAssetId)>,
+}
+
+impl LoadingAssets {
+    pub fn track<A: Asset>(&mut self, label: impl Into<String>, handle: &Handle<A>) {
+        self.assets.push((label.into(), handle.id().untyped()));
+    }
+}
+
+#[derive(Component)]
+struct LoadingScreen;
+
+#[derive(Component)]
+struct LoadingText;
+
+#[derive(Component)]
+struct LoadingBar;
+
+fn spawn_loading_screen(mut commands: Commands) {
+    commands.spawn((Camera2dBundle::default(), LoadingScreen));
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(12.0),
+                    ..default()
+                },
+                ..default()
+            },
+            LoadingScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "Loading...",
+                    TextStyle {
+                        font_size: 24.0,
+                        ..default()
+                    },
+                ),
+                LoadingText,
+            ));
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(PROGRESS_BAR_WIDTH),
+                        height: Val::Px(12.0),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.15, 0.15, 0.15).into(),
+                    ..default()
+                })
+                .with_children(|bar| {
+                    bar.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Px(0.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: Color::rgb(0.3, 0.6, 0.3).into(),
+                            ..default()
+                        },
+                        LoadingBar,
+                    ));
+                });
+        });
+}
+
+fn update_loading_progress(
+    asset_server: Res<AssetServer>,
+    loading: Res<LoadingAssets>,
+    mut text: Query<&mut Text, With<LoadingText>>,
+    mut bar: Query<&mut Style, With<LoadingBar>>,
+    mut reason: ResMut<DisconnectReason>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    let mut loaded = 0;
+    for (label, id) in &loading.assets {
+        match asset_server.get_recursive_dependency_load_state(*id) {
+            Some(RecursiveDependencyLoadState::Loaded) => loaded += 1,
+            Some(RecursiveDependencyLoadState::Failed) => {
+                error!("Failed to load {}", label);
+                reason.0 = Some(format!("failed to load {}", label));
+                next_state.set(GameClientState::MainMenu);
+                return;
+            }
+            Some(_) => {}
+            // the asset server hasn't started on it yet
+            None => {}
+        }
+    }
+
+    let total = loading.assets.len();
+    let progress = if total == 0 {
+        1.0
+    } else {
+        loaded as f32 / total as f32
+    };
+
+    for mut text in text.iter_mut() {
+        text.sections[0].value = format!("Loading... {}/{}", loaded, total);
+    }
+    for mut style in bar.iter_mut() {
+        style.width = Val::Px(PROGRESS_BAR_WIDTH * progress);
+    }
+
+    if loaded == total {
+        info!("Loaded {} assets", total);
+        next_state.set(GameClientState::Connecting);
+    }
+}
+
+fn despawn_loading_screen(mut commands: Commands, screen: Query<Entity, With<LoadingScreen>>) {
+    for entity in screen.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn clear_loading_assets(mut loading: ResMut<LoadingAssets>) {
+    loading.assets.clear();
+}
@@ -1,14 +1,38 @@
+mod appearance;
+mod camera;
 mod client;
+mod collision;
+mod controls;
+mod handshake;
+mod level;
+mod loading;
+mod menu;
 mod movement;
+mod nameplate;
+mod ownership;
+mod priority;
 mod protocol;
+mod reconnect;
+mod respawn;
+mod roster;
 mod server;
+mod settings;
 mod shared;
+mod spawn;
+mod spectator;
+mod teleport;
+mod tuning;
 mod world;
 
 use crate::client::{build_client_net_config, client_app};
-use crate::server::{build_server_net_config, server_app};
+use crate::respawn::OutOfBounds;
+use crate::server::{build_server_net_config, server_app, ReconnectGracePeriod};
+use crate::shared::DEFAULT_SERVER_SEND_BUDGET_BYTES;
+use crate::spawn::SpawnStrategy;
+use crate::spectator::SpectatorMode;
 use clap::Parser;
 use std::env;
+use std::time::Duration;
 
 #[derive(Parser, Clone, Copy)]
 #[command(version, about, long_about = None)]
@@ -18,6 +42,35 @@ struct Cli {
 
     #[arg(short, long, default_value_t = 1234)]
     client_id: u64,
+
+    /// Watch the game with a free-fly camera instead of playing
+    #[arg(long)]
+    spectate: bool,
+
+    /// How the server picks among the level's spawn points
+    #[arg(long, value_enum, default_value_t = SpawnStrategy::default())]
+    spawn_strategy: SpawnStrategy,
+
+    /// Players falling below this height respawn at a spawn point
+    #[arg(
+        long,
+        default_value_t = OutOfBounds::default().kill_height,
+        allow_negative_numbers = true
+    )]
+    kill_height: f32,
+
+    /// Bytes the server may send to each client per send interval, what doesn't fit waits for
+    /// the next one
+    #[arg(long, default_value_t = DEFAULT_SERVER_SEND_BUDGET_BYTES)]
+    send_budget_bytes: u32,
+
+    /// Players further than this from the origin along X or Z respawn at a spawn point
+    #[arg(long, default_value_t = OutOfBounds::default().half_extent)]
+    level_half_extent: f32,
+
+    /// Seconds a disconnected player stays in the world waiting for its client to come back
+    #[arg(long, default_value_t = ReconnectGracePeriod::default().0.as_secs_f32())]
+    reconnect_grace_period: f32,
 }
 
 fn main() {
@@ -29,10 +82,20 @@ fn main() {
     println!("client_id: {:?}", cli.client_id);
 
     if cli.listen_server {
-        let mut server_app = server_app(build_server_net_config());
+        let mut server_app = server_app(build_server_net_config(), cli.send_budget_bytes);
+        server_app.insert_resource(cli.spawn_strategy);
+        server_app.insert_resource(OutOfBounds {
+            kill_height: cli.kill_height,
+            half_extent: cli.level_half_extent,
+            ..OutOfBounds::default()
+        });
+        server_app.insert_resource(ReconnectGracePeriod(Duration::from_secs_f32(
+            cli.reconnect_grace_period,
+        )));
         server_app.run();
     } else {
         let mut client_app = client_app(build_client_net_config(cli.client_id, "127.0.0.1:5000"));
+        client_app.insert_resource(SpectatorMode(cli.spectate));
         client_app.run();
     }
 
@@ -0,0 +1,527 @@
+use std::net::SocketAddr;
+
+use bevy::prelude::*;
+use lightyear::prelude::client::ClientConfig;
+
+use crate::client::{
+    build_client_net_config, get_local_client_id, get_server_addr, GameClientState,
+};
+use crate::protocol::Cosmetic;
+use crate::settings::{self, ClientSettings, CLIENT_SETTINGS_FILE};
+
+const FIELD_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const FOCUSED_FIELD_COLOR: Color = Color::rgb(0.25, 0.25, 0.3);
+pub(crate) const BUTTON_COLOR: Color = Color::rgb(0.2, 0.35, 0.2);
+pub(crate) const HOVERED_BUTTON_COLOR: Color = Color::rgb(0.25, 0.45, 0.25);
+pub(crate) const ERROR_COLOR: Color = Color::rgb(0.9, 0.3, 0.3);
+
+/// Colors players can pick from.
+const PLAYER_COLORS: [Color; 8] = [
+    Color::rgb(0.8, 0.7, 0.6),
+    Color::rgb(0.85, 0.3, 0.3),
+    Color::rgb(0.9, 0.6, 0.2),
+    Color::rgb(0.9, 0.85, 0.3),
+    Color::rgb(0.35, 0.75, 0.35),
+    Color::rgb(0.3, 0.7, 0.85),
+    Color::rgb(0.35, 0.4, 0.85),
+    Color::rgb(0.75, 0.4, 0.8),
+];
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DisconnectReason>()
+            .insert_resource(settings::load::<ClientSettings>(CLIENT_SETTINGS_FILE))
+            .add_systems(Startup, init_connect_form)
+            .add_systems(OnEnter(GameClientState::MainMenu), spawn_menu)
+            .add_systems(OnExit(GameClientState::MainMenu), despawn_menu)
+            .add_systems(
+                Update,
+                (
+                    focus_field,
+                    type_into_field,
+                    update_fields,
+                    update_error,
+                    button_colors,
+                    pick_recent_server,
+                    cycle_appearance,
+                    update_appearance_buttons,
+                    open_controls,
+                    connect,
+                )
+                    .chain()
+                    .run_if(in_state(GameClientState::MainMenu)),
+            );
+    }
+}
+
+/// Why we last left a game, or why connecting failed. Shown on the menu until the next
+/// connection attempt.
+#[derive(Resource, Default)]
+pub(crate) struct DisconnectReason(pub(crate) Option<String>);
+
+/// What's currently typed into the menu.
+#[derive(Resource, Default)]
+pub(crate) struct ConnectForm {
+    pub(crate) server_addr: String,
+    pub(crate) player_name: String,
+    pub(crate) client_id: String,
+    pub(crate) player_color: Color,
+    pub(crate) cosmetic: Cosmetic,
+    focused: Option<FormField>,
+}
+
+impl ConnectForm {
+    fn field_mut(&mut self, field: FormField) -> &mut String {
+        match field {
+            FormField::ServerAddr => &mut self.server_addr,
+            FormField::PlayerName => &mut self.player_name,
+            FormField::ClientId => &mut self.client_id,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+enum FormField {
+    ServerAddr,
+    PlayerName,
+    ClientId,
+}
+
+#[derive(Component)]
+struct FieldText(FormField);
+
+#[derive(Component)]
+struct ErrorText;
+
+#[derive(Component)]
+struct ConnectButton;
+
+#[derive(Component)]
+struct ControlsButton;
+
+#[derive(Component)]
+struct RecentServerButton(String);
+
+#[derive(Component)]
+struct ColorButton;
+
+#[derive(Component)]
+struct CosmeticButton;
+
+#[derive(Component)]
+struct CosmeticText;
+
+#[derive(Component)]
+struct MenuRoot;
+
+#[derive(Component)]
+struct MenuCamera;
+
+/// Pre-fills the form from the command line and the saved settings.
+fn init_connect_form(
+    mut commands: Commands,
+    client_config: Res<ClientConfig>,
+    saved: Res<ClientSettings>,
+) {
+    let server_addr = saved.recent_servers.first().cloned().unwrap_or_else(|| {
+        get_server_addr(&client_config.net)
+            .map(|addr| addr.to_string())
+            .unwrap_or_default()
+    });
+
+    commands.insert_resource(ConnectForm {
+        server_addr,
+        player_name: saved.player_name.clone(),
+        // not saved, so several clients on one machine keep the ids they were started with
+        client_id: get_local_client_id(&client_config.net)
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        player_color: saved.player_color,
+        cosmetic: saved.cosmetic,
+        focused: None,
+    });
+}
+
+fn spawn_menu(mut commands: Commands, saved: Res<ClientSettings>) {
+    commands.spawn((Camera2dBundle::default(), MenuCamera));
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(12.0),
+                    ..default()
+                },
+                ..default()
+            },
+            MenuRoot,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Cubes",
+                TextStyle {
+                    font_size: 64.0,
+                    ..default()
+                },
+            ));
+
+            spawn_field(parent, "Server address", FormField::ServerAddr);
+            spawn_field(parent, "Player name", FormField::PlayerName);
+            spawn_field(parent, "Client id", FormField::ClientId);
+            spawn_appearance_buttons(parent);
+
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 20.0,
+                        color: ERROR_COLOR,
+                        ..default()
+                    },
+                ),
+                ErrorText,
+            ));
+
+            parent
+                .spawn((button_bundle(), ConnectButton))
+                .with_children(|button| {
+                    button.spawn(TextBundle::from_section("Connect", TextStyle::default()));
+                });
+
+            parent
+                .spawn((button_bundle(), ControlsButton))
+                .with_children(|button| {
+                    button.spawn(TextBundle::from_section("Controls", TextStyle::default()));
+                });
+
+            if !saved.recent_servers.is_empty() {
+                parent.spawn(TextBundle::from_section(
+                    "Recent servers",
+                    TextStyle {
+                        font_size: 20.0,
+                        ..default()
+                    },
+                ));
+            }
+
+            for server_addr in &saved.recent_servers {
+                parent
+                    .spawn((button_bundle(), RecentServerButton(server_addr.clone())))
+                    .with_children(|button| {
+                        button.spawn(TextBundle::from_section(
+                            server_addr.clone(),
+                            TextStyle::default(),
+                        ));
+                    });
+            }
+        });
+}
+
+fn spawn_field(parent: &mut ChildBuilder, label: &str, field: FormField) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                column_gap: Val::Px(12.0),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(
+                TextBundle::from_section(
+                    label,
+                    TextStyle {
+                        font_size: 20.0,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    width: Val::Px(160.0),
+                    ..default()
+                }),
+            );
+
+            row.spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Px(320.0),
+                        padding: UiRect::all(Val::Px(6.0)),
+                        ..default()
+                    },
+                    background_color: FIELD_COLOR.into(),
+                    ..default()
+                },
+                field,
+            ))
+            .with_children(|input| {
+                input.spawn((
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 20.0,
+                            ..default()
+                        },
+                    ),
+                    FieldText(field),
+                ));
+            });
+        });
+}
+
+fn spawn_appearance_buttons(parent: &mut ChildBuilder) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                column_gap: Val::Px(12.0),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(
+                TextBundle::from_section(
+                    "Appearance",
+                    TextStyle {
+                        font_size: 20.0,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    width: Val::Px(160.0),
+                    ..default()
+                }),
+            );
+
+            // the background shows the picked color, see `update_appearance_buttons`
+            row.spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Px(48.0),
+                        height: Val::Px(32.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                ColorButton,
+            ));
+
+            row.spawn((button_bundle(), CosmeticButton))
+                .with_children(|button| {
+                    button.spawn((
+                        TextBundle::from_section("", TextStyle::default()),
+                        CosmeticText,
+                    ));
+                });
+        });
+}
+
+pub(crate) fn button_bundle() -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            padding: UiRect::axes(Val::Px(24.0), Val::Px(8.0)),
+            ..default()
+        },
+        background_color: BUTTON_COLOR.into(),
+        ..default()
+    }
+}
+
+fn despawn_menu(
+    mut commands: Commands,
+    mut form: ResMut<ConnectForm>,
+    menu: Query<Entity, Or<(With<MenuRoot>, With<MenuCamera>)>>,
+) {
+    form.focused = None;
+    for entity in menu.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn focus_field(
+    mut form: ResMut<ConnectForm>,
+    fields: Query<(&Interaction, &FormField), Changed<Interaction>>,
+) {
+    for (interaction, field) in fields.iter() {
+        if *interaction == Interaction::Pressed {
+            form.focused = Some(*field);
+        }
+    }
+}
+
+fn type_into_field(
+    mut form: ResMut<ConnectForm>,
+    mut characters: EventReader<ReceivedCharacter>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    let Some(field) = form.focused else {
+        characters.clear();
+        return;
+    };
+
+    let value = form.field_mut(field);
+    if keys.just_pressed(KeyCode::Backspace) {
+        value.pop();
+    }
+    for event in characters.read() {
+        value.extend(event.char.chars().filter(|c| !c.is_control()));
+    }
+}
+
+fn update_fields(
+    form: Res<ConnectForm>,
+    mut texts: Query<(&mut Text, &FieldText)>,
+    mut backgrounds: Query<(&mut BackgroundColor, &FormField)>,
+) {
+    for (mut text, field) in texts.iter_mut() {
+        let value = match field.0 {
+            FormField::ServerAddr => &form.server_addr,
+            FormField::PlayerName => &form.player_name,
+            FormField::ClientId => &form.client_id,
+        };
+        let cursor = if form.focused == Some(field.0) {
+            "_"
+        } else {
+            ""
+        };
+        text.sections[0].value = format!("{}{}", value, cursor);
+    }
+
+    for (mut background, field) in backgrounds.iter_mut() {
+        *background = if form.focused == Some(*field) {
+            FOCUSED_FIELD_COLOR.into()
+        } else {
+            FIELD_COLOR.into()
+        };
+    }
+}
+
+fn update_error(reason: Res<DisconnectReason>, mut text: Query<&mut Text, With<ErrorText>>) {
+    for mut text in text.iter_mut() {
+        text.sections[0].value = reason.0.clone().unwrap_or_default();
+    }
+}
+
+fn button_colors(
+    mut buttons: Query<
+        (&Interaction, &mut BackgroundColor),
+        (
+            Changed<Interaction>,
+            Or<(
+                With<ConnectButton>,
+                With<ControlsButton>,
+                With<RecentServerButton>,
+                With<CosmeticButton>,
+            )>,
+        ),
+    >,
+) {
+    for (interaction, mut background) in buttons.iter_mut() {
+        *background = match interaction {
+            Interaction::Hovered | Interaction::Pressed => HOVERED_BUTTON_COLOR.into(),
+            Interaction::None => BUTTON_COLOR.into(),
+        };
+    }
+}
+
+fn pick_recent_server(
+    mut form: ResMut<ConnectForm>,
+    buttons: Query<(&Interaction, &RecentServerButton), Changed<Interaction>>,
+) {
+    for (interaction, recent) in buttons.iter() {
+        if *interaction == Interaction::Pressed {
+            form.server_addr = recent.0.clone();
+        }
+    }
+}
+
+fn cycle_appearance(
+    mut form: ResMut<ConnectForm>,
+    color_button: Query<&Interaction, (Changed<Interaction>, With<ColorButton>)>,
+    cosmetic_button: Query<&Interaction, (Changed<Interaction>, With<CosmeticButton>)>,
+) {
+    if color_button.iter().any(|i| *i == Interaction::Pressed) {
+        let next = PLAYER_COLORS
+            .iter()
+            .position(|color| *color == form.player_color)
+            .map_or(0, |index| (index + 1) % PLAYER_COLORS.len());
+        form.player_color = PLAYER_COLORS[next];
+    }
+
+    if cosmetic_button.iter().any(|i| *i == Interaction::Pressed) {
+        let next = Cosmetic::ALL
+            .iter()
+            .position(|cosmetic| *cosmetic == form.cosmetic)
+            .map_or(0, |index| (index + 1) % Cosmetic::ALL.len());
+        form.cosmetic = Cosmetic::ALL[next];
+    }
+}
+
+fn update_appearance_buttons(
+    form: Res<ConnectForm>,
+    mut color_button: Query<&mut BackgroundColor, With<ColorButton>>,
+    mut cosmetic_text: Query<&mut Text, With<CosmeticText>>,
+) {
+    for mut background in color_button.iter_mut() {
+        *background = form.player_color.into();
+    }
+    for mut text in cosmetic_text.iter_mut() {
+        text.sections[0].value = format!("{:?}", form.cosmetic);
+    }
+}
+
+fn open_controls(
+    button: Query<&Interaction, (Changed<Interaction>, With<ControlsButton>)>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    if button.iter().any(|i| *i == Interaction::Pressed) {
+        next_state.set(GameClientState::Controls);
+    }
+}
+
+fn connect(
+    keys: Res<ButtonInput<KeyCode>>,
+    button: Query<&Interaction, (Changed<Interaction>, With<ConnectButton>)>,
+    form: Res<ConnectForm>,
+    mut saved: ResMut<ClientSettings>,
+    mut client_config: ResMut<ClientConfig>,
+    mut reason: ResMut<DisconnectReason>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    let pressed = button
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed);
+    if !pressed && !keys.just_pressed(KeyCode::Enter) {
+        return;
+    }
+
+    let server_addr = form.server_addr.trim();
+    if server_addr.parse::<SocketAddr>().is_err() {
+        reason.0 = Some(format!("invalid server address: {:?}", server_addr));
+        return;
+    }
+    let Ok(client_id) = form.client_id.trim().parse::<u64>() else {
+        reason.0 = Some(format!("invalid client id: {:?}", form.client_id));
+        return;
+    };
+
+    info!("Connecting to {} as {}", server_addr, client_id);
+    client_config.net = build_client_net_config(client_id, server_addr);
+
+    saved.player_name = form.player_name.trim().to_string();
+    saved.player_color = form.player_color;
+    saved.cosmetic = form.cosmetic;
+    saved.remember_server(server_addr);
+    settings::save(CLIENT_SETTINGS_FILE, &*saved);
+
+    reason.0 = None;
+    next_state.set(GameClientState::Loading);
+}
@@ -1,19 +1,38 @@
 use bevy::prelude::*;
 use bevy_tnua::{
-    builtins::{TnuaBuiltinJump, TnuaBuiltinWalk},
+    builtins::{TnuaBuiltinCrouch, TnuaBuiltinDash, TnuaBuiltinJump, TnuaBuiltinWalk},
     control_helpers::TnuaCrouchEnforcerPlugin,
     controller::{TnuaController, TnuaControllerPlugin},
     TnuaUserControlsSystemSet,
 };
 use bevy_tnua_xpbd3d::TnuaXpbd3dPlugin;
 use bevy_xpbd_3d::{
-    components::{Position, Rotation},
-    plugins::{setup::Physics, PhysicsPlugins},
+    components::Rotation,
+    plugins::{
+        collision::Collider,
+        setup::Physics,
+        spatial_query::{SpatialQuery, SpatialQueryFilter},
+        PhysicsPlugins,
+    },
 };
 use leafwing_input_manager::action_state::ActionState;
-use lightyear::{prelude::client::*, shared::tick_manager::TickManager};
+use leafwing_input_manager::axislike::DualAxisData;
+use leafwing_input_manager::plugin::InputManagerSystem;
+use lightyear::prelude::client::*;
 
-use crate::protocol::{LocalPlayer, MyProtocol, PlayerActions};
+use crate::camera::FollowCamera;
+use crate::client::PlayerCamera;
+use crate::collision::GameLayer;
+use crate::protocol::{
+    DashCooldown, LocalPlayer, MovementSettings, MovementState, MyProtocol, PlayerActions,
+};
+use crate::shared::FIXED_TIMESTEP_HZ;
+
+/// Analog inputs are rounded to this many steps per unit before being sent. Sticks never rest
+/// at exactly the same value, so without it every frame would send a new input.
+const AXIS_STEPS: f32 = 32.0;
+/// Steps per radian for `CameraYaw`.
+const YAW_STEPS: f32 = 256.0;
 
 pub struct MovementPlugin;
 
@@ -25,76 +44,169 @@ impl Plugin for MovementPlugin {
                 ..default()
             },
         ))
-        .add_systems(Update, (draw_interpolated_boxes, draw_confirmed_boxes));
+        // before lightyear buffers the inputs for this frame's ticks
+        .add_systems(
+            PreUpdate,
+            (write_camera_yaw, quantize_axis_inputs)
+                .chain()
+                .after(InputManagerSystem::Update),
+        );
     }
 }
 
-fn player_movement(
-    tick_manager: Res<TickManager>,
-    mut action_query: Query<
-        (
-            Entity,
-            &Position,
-            &mut TnuaController,
-            &ActionState<PlayerActions>,
-        ),
-        With<LocalPlayer>,
-    >,
+/// Whether a crouched player has no room above it to stand up. Worked out from positions, which
+/// are rolled back, rather than kept between ticks like `TnuaCrouchEnforcer` does.
+pub(crate) fn headroom_blocked(
+    spatial_query: &SpatialQuery,
+    entity: Entity,
+    position: Vec3,
+    settings: &MovementSettings,
+) -> bool {
+    spatial_query
+        .cast_shape(
+            &Collider::cuboid(0.98, 0.98, 0.98),
+            position,
+            Quat::IDENTITY,
+            Direction3d::Y,
+            -settings.crouch_float_offset,
+            true,
+            SpatialQueryFilter::from_mask([GameLayer::Level, GameLayer::Player])
+                .with_excluded_entities([entity]),
+        )
+        .is_some()
+}
+
+/// How many ticks a dash lasts.
+fn dash_ticks(settings: &MovementSettings) -> u16 {
+    (settings.dash_distance / settings.dash_speed * FIXED_TIMESTEP_HZ as f32).ceil() as u16
+}
+
+fn quantize(value: f32, steps: f32) -> f32 {
+    (value * steps).round() / steps
+}
+
+/// Rounds the analog inputs, so that what the client predicts with is exactly what the server
+/// gets, and tiny stick movements don't each cost a message.
+fn quantize_axis_inputs(mut players: Query<&mut ActionState<PlayerActions>, With<LocalPlayer>>) {
+    for mut action_state in players.iter_mut() {
+        let movement = action_state
+            .clamped_axis_pair(&PlayerActions::Move)
+            .map_or(Vec2::ZERO, |axis| axis.xy());
+        let movement = Vec2::new(
+            quantize(movement.x, AXIS_STEPS),
+            quantize(movement.y, AXIS_STEPS),
+        );
+        // lightyear only sends the values of pressed actions, and a release is what tells the
+        // server the stick is back at rest
+        if movement == Vec2::ZERO {
+            action_state.release(&PlayerActions::Move);
+        } else {
+            action_state.press(&PlayerActions::Move);
+        }
+        let data = action_state.action_data_mut_or_default(&PlayerActions::Move);
+        data.axis_pair = Some(DualAxisData::from_xy(movement));
+        data.value = movement.length();
+
+        let yaw = action_state.action_data_mut_or_default(&PlayerActions::CameraYaw);
+        yaw.value = quantize(yaw.value, YAW_STEPS);
+    }
+}
+
+/// Feeds the yaw of the camera following the local player into its `CameraYaw` action, so the
+/// server moves it the same way the client predicts.
+fn write_camera_yaw(
+    cameras: Query<&FollowCamera, With<PlayerCamera>>,
+    mut players: Query<&mut ActionState<PlayerActions>, With<LocalPlayer>>,
 ) {
-    for (entity, position, mut controller, action_state) in action_query.iter_mut() {
-        info!(?entity, tick = ?tick_manager.tick(), ?position, actions = ?action_state.get_pressed(), "applying movement to player");
+    let Some(camera) = cameras.iter().find(|camera| camera.target.is_none()) else {
+        return;
+    };
 
-        shared_movement_behaviour(&mut controller, action_state);
+    for mut action_state in players.iter_mut() {
+        // lightyear only sends the values of pressed actions, and a yaw of 0 is a direction too,
+        // so the action is held for as long as there's a camera
+        action_state.press(&PlayerActions::CameraYaw);
+        action_state
+            .action_data_mut_or_default(&PlayerActions::CameraYaw)
+            .value = camera.yaw;
     }
 }
 
+/// Called once per tick, including when resimulating a rollback.
 pub fn shared_movement_behaviour(
     controller: &mut TnuaController,
+    state: &mut MovementState,
+    dash_cooldown: &mut DashCooldown,
+    headroom_blocked: impl FnOnce() -> bool,
     action: &ActionState<PlayerActions>,
+    settings: &MovementSettings,
 ) {
-    let mut direction = Vec3::ZERO;
+    // stick up is forward, which is -Z before turning towards where the camera looks. A
+    // released `Move` keeps the last value it was sent with
+    let input = if action.pressed(&PlayerActions::Move) {
+        action
+            .clamped_axis_pair(&PlayerActions::Move)
+            .map_or(Vec2::ZERO, |axis| axis.xy())
+            .clamp_length_max(1.0)
+    } else {
+        Vec2::ZERO
+    };
+    let yaw = Quat::from_rotation_y(action.value(&PlayerActions::CameraYaw));
+    let direction = yaw * Vec3::new(input.x, 0.0, -input.y);
 
-    if action.pressed(&PlayerActions::Up) {
-        direction -= Vec3::Z;
-    }
-
-    if action.pressed(&PlayerActions::Down) {
-        direction += Vec3::Z;
-    }
-
-    if action.pressed(&PlayerActions::Left) {
-        direction -= Vec3::X;
-    }
-
-    if action.pressed(&PlayerActions::Right) {
-        direction += Vec3::X;
-    }
+    // a player who let go stays down while something is right above it
+    state.crouched =
+        action.pressed(&PlayerActions::Crouch) || (state.crouched && headroom_blocked());
+    let speed = if state.crouched {
+        settings.walk_speed * settings.crouch_multiplier
+    } else if action.pressed(&PlayerActions::Sprint) {
+        settings.walk_speed * settings.sprint_multiplier
+    } else {
+        settings.walk_speed
+    };
 
     controller.basis(TnuaBuiltinWalk {
-        desired_velocity: direction.normalize_or_zero() * 1.0,
-        float_height: 1.0,
+        desired_velocity: direction * speed,
+        float_height: settings.float_height,
         ..Default::default()
     });
 
+    // Tnua runs one action at a time, the last one fed wins
+    if state.crouched {
+        controller.action(TnuaBuiltinCrouch {
+            float_offset: settings.crouch_float_offset,
+            ..Default::default()
+        });
+    }
+
     if action.pressed(&PlayerActions::Jump) {
         controller.action(TnuaBuiltinJump {
-            height: 2.0,
+            height: settings.jump_height,
             ..Default::default()
         });
     }
-}
 
-fn draw_interpolated_boxes(
-    mut gizmos: Gizmos,
-    players: Query<&Transform, Or<(With<Interpolated>, With<LocalPlayer>)>>,
-) {
-    for position in &players {
-        gizmos.cuboid(position.with_scale(Vec3::splat(1.0)), Color::BLUE);
+    dash_cooldown.remaining_ticks = dash_cooldown.remaining_ticks.saturating_sub(1);
+    // a dash has to be fed until it's done, the cooldown only gates starting one
+    if state.dash_ticks == 0
+        && action.pressed(&PlayerActions::Dash)
+        && dash_cooldown.remaining_ticks == 0
+    {
+        state.dash_ticks = dash_ticks(settings);
+        dash_cooldown.remaining_ticks = settings.dash_cooldown_ticks;
     }
-}
-
-fn draw_confirmed_boxes(mut gizmos: Gizmos, players: Query<&Transform, With<Confirmed>>) {
-    for position in &players {
-        gizmos.cuboid(position.with_scale(Vec3::splat(1.0)), Color::RED);
+    if state.dash_ticks > 0 {
+        state.dash_ticks -= 1;
+        let dash_direction = if direction == Vec3::ZERO {
+            yaw * Vec3::NEG_Z
+        } else {
+            direction.normalize()
+        };
+        controller.action(TnuaBuiltinDash {
+            displacement: dash_direction * settings.dash_distance,
+            speed: settings.dash_speed,
+            allow_in_air: true,
+            ..Default::default()
+        });
     }
 }
@@ -0,0 +1,134 @@
+use bevy::prelude::*;
+use bevy::ui::UiSystem;
+use lightyear::prelude::client::*;
+
+use crate::camera::follow_players;
+use crate::client::PlayerCamera;
+use crate::protocol::{LocalPlayer, PlayerProfile};
+
+/// How far above the player's center the name is shown.
+const NAMEPLATE_HEIGHT: f32 = 1.0;
+/// Nameplates start fading out at this distance from the camera...
+const FADE_START_DISTANCE: f32 = 15.0;
+/// ...and are gone at this one.
+const FADE_END_DISTANCE: f32 = 40.0;
+
+/// Shows each remote player's name above its cube.
+///
+/// Nameplates are UI text moved to where the player is on screen every frame, so they always
+/// face the camera. They follow the interpolated entity, which is what's drawn, rather than the
+/// confirmed state.
+pub struct NameplatePlugin;
+
+impl Plugin for NameplatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (spawn_nameplates, update_nameplate_names, despawn_nameplates),
+        )
+        .add_systems(
+            PostUpdate,
+            place_nameplates
+                .after(follow_players)
+                .before(UiSystem::Layout),
+        );
+    }
+}
+
+/// UI text showing the name of `target`.
+#[derive(Component)]
+struct Nameplate {
+    target: Entity,
+}
+
+fn spawn_nameplates(
+    players: Query<(Entity, &PlayerProfile), (Added<Interpolated>, Without<LocalPlayer>)>,
+    mut commands: Commands,
+) {
+    for (entity, profile) in players.iter() {
+        commands.spawn((
+            TextBundle::from_section(
+                profile.name.clone(),
+                TextStyle {
+                    font_size: 20.0,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            }),
+            Nameplate { target: entity },
+        ));
+    }
+}
+
+fn update_nameplate_names(
+    profiles: Query<&PlayerProfile, Changed<PlayerProfile>>,
+    mut nameplates: Query<(&Nameplate, &mut Text)>,
+) {
+    for (nameplate, mut text) in nameplates.iter_mut() {
+        if let Ok(profile) = profiles.get(nameplate.target) {
+            text.sections[0].value = profile.name.clone();
+        }
+    }
+}
+
+fn despawn_nameplates(
+    nameplates: Query<(Entity, &Nameplate)>,
+    players: Query<(), With<PlayerProfile>>,
+    mut commands: Commands,
+) {
+    for (entity, nameplate) in nameplates.iter() {
+        if !players.contains(nameplate.target) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Runs before the UI layout so nameplates don't trail a frame behind. Players and the camera
+/// have no parent, so their `Transform` is already where they'll be drawn this frame.
+fn place_nameplates(
+    camera: Query<(&Camera, &Transform), With<PlayerCamera>>,
+    players: Query<&Transform, (Without<LocalPlayer>, Without<PlayerCamera>)>,
+    mut nameplates: Query<(&Nameplate, &Node, &mut Style, &mut Text, &mut Visibility)>,
+) {
+    let Ok((camera, camera_transform)) = camera.get_single() else {
+        for (_, _, _, _, mut visibility) in nameplates.iter_mut() {
+            *visibility = Visibility::Hidden;
+        }
+        return;
+    };
+
+    for (nameplate, node, mut style, mut text, mut visibility) in nameplates.iter_mut() {
+        let Ok(player_transform) = players.get(nameplate.target) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+
+        let position = player_transform.translation + Vec3::Y * NAMEPLATE_HEIGHT;
+        let distance = camera_transform.translation.distance(position);
+        // `world_to_viewport` gives nothing for points behind the camera
+        let Some(screen_position) =
+            camera.world_to_viewport(&GlobalTransform::from(*camera_transform), position)
+        else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        if distance >= FADE_END_DISTANCE {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+
+        let alpha = 1.0
+            - ((distance - FADE_START_DISTANCE) / (FADE_END_DISTANCE - FADE_START_DISTANCE))
+                .clamp(0.0, 1.0);
+        text.sections[0].style.color.set_a(alpha);
+
+        // centered above the player
+        let size = node.size();
+        style.left = Val::Px(screen_position.x - size.x / 2.0);
+        style.top = Val::Px(screen_position.y - size.y);
+        *visibility = Visibility::Visible;
+    }
+}
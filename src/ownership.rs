@@ -0,0 +1,51 @@
+use bevy::ecs::query::QueryFilter;
+use bevy::prelude::*;
+use lightyear::prelude::server::DisconnectEvent;
+use lightyear::prelude::*;
+
+/// Marks a server entity as belonging to a client. Everything a client owns is despawned
+/// when that client disconnects.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OwnedBy(pub ClientId);
+
+/// Owned entities with this marker survive their owner disconnecting; whoever adds it is
+/// responsible for cleaning them up.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct KeepOnDisconnect;
+
+pub struct OwnershipPlugin;
+
+impl Plugin for OwnershipPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, despawn_owned_entities);
+    }
+}
+
+/// All entities owned by `client_id`, however many there are.
+pub(crate) fn owned_entities<F: QueryFilter>(
+    owned: &Query<(Entity, &OwnedBy), F>,
+    client_id: ClientId,
+) -> Vec<Entity> {
+    owned
+        .iter()
+        .filter(|(_, owner)| owner.0 == client_id)
+        .map(|(entity, _)| entity)
+        .collect()
+}
+
+pub(crate) fn despawn_owned_entities(
+    mut disconnections: EventReader<DisconnectEvent>,
+    owned: Query<(Entity, &OwnedBy), Without<KeepOnDisconnect>>,
+    mut commands: Commands,
+) {
+    for disconnection in disconnections.read() {
+        let client_id = *disconnection.context();
+        for entity in owned_entities(&owned, client_id) {
+            info!(
+                "Despawning {:?} owned by disconnected client {:?}",
+                entity, client_id
+            );
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_xpbd_3d::components::Position;
+use lightyear::prelude::server::ConnectEvent;
+use lightyear::prelude::*;
+
+use crate::protocol::{PlayerId, Replicate, ServerConnectionManager};
+
+/// Priority every replicated entity starts with.
+const BASE_PRIORITY: f32 = 1.0;
+/// Extra priority for an entity standing right next to the receiving player, fading out with
+/// distance.
+const PROXIMITY_PRIORITY: f32 = 4.0;
+/// Distance at which the proximity bonus has completely faded out.
+const PROXIMITY_RADIUS: f32 = 32.0;
+/// Extra priority for an entity that has just moved, fading out over `CHANGE_DECAY_SECS`.
+const CHANGE_PRIORITY: f32 = 2.0;
+const CHANGE_DECAY_SECS: f32 = 0.5;
+/// Priorities are only sent to lightyear when they move by more than this.
+const PRIORITY_EPSILON: f32 = 0.25;
+
+/// Feeds lightyear's priority accumulator.
+///
+/// When a connection runs out of bandwidth (see `server_packet_config`), lightyear keeps the
+/// replication groups it couldn't send and adds their base priority to them every send interval,
+/// so low priority updates are deferred until they've accumulated enough rather than dropped.
+/// This plugin keeps that base priority up to date, separately for every receiving client.
+pub struct PriorityPlugin;
+
+impl Plugin for PriorityPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (track_recent_changes, update_replication_priority)
+                .chain()
+                .before(MainSet::Send),
+        );
+    }
+}
+
+/// The priority currently applied to the entity's `ReplicationGroup`, per receiving client.
+#[derive(Component, Debug)]
+pub(crate) struct ReplicationPriority {
+    pub(crate) values: HashMap<ClientId, f32>,
+    since_change: f32,
+}
+
+impl Default for ReplicationPriority {
+    fn default() -> Self {
+        Self {
+            values: HashMap::default(),
+            since_change: CHANGE_DECAY_SECS,
+        }
+    }
+}
+
+fn track_recent_changes(
+    time: Res<Time>,
+    mut commands: Commands,
+    untracked: Query<
+        Entity,
+        (
+            With<Replicate>,
+            With<Position>,
+            Without<ReplicationPriority>,
+        ),
+    >,
+    mut tracked: Query<(Ref<Position>, &mut ReplicationPriority)>,
+) {
+    for entity in untracked.iter() {
+        commands
+            .entity(entity)
+            .insert(ReplicationPriority::default());
+    }
+
+    for (position, mut priority) in tracked.iter_mut() {
+        if position.is_changed() {
+            priority.since_change = 0.0;
+        } else {
+            priority.since_change += time.delta_seconds();
+        }
+    }
+}
+
+/// Spectators have no player to be close to, so they keep lightyear's default priority.
+fn update_replication_priority(
+    receivers: Query<(&PlayerId, &Position)>,
+    mut replicated: Query<(
+        Entity,
+        Option<&PlayerId>,
+        &Position,
+        &mut ReplicationPriority,
+    )>,
+    mut connections: EventReader<ConnectEvent>,
+    mut server: ResMut<ServerConnectionManager>,
+) {
+    // a new connection, or a reconnection, starts again from lightyear's default priority
+    for connection in connections.read() {
+        for (_, _, _, mut priority) in replicated.iter_mut() {
+            priority.values.remove(connection.context());
+        }
+    }
+
+    for (entity, owner, position, mut priority) in replicated.iter_mut() {
+        let change = (1.0 - priority.since_change / CHANGE_DECAY_SECS).max(0.0);
+        // entities are in their own replication group unless told otherwise
+        let group = ReplicationGroupId(entity.to_bits());
+
+        for (receiver, receiver_position) in receivers.iter() {
+            // a client always receives its own player through prediction
+            if Some(receiver) == owner {
+                continue;
+            }
+
+            let distance = receiver_position.distance(position.0);
+            let proximity = (1.0 - distance / PROXIMITY_RADIUS).max(0.0);
+            let value = BASE_PRIORITY + PROXIMITY_PRIORITY * proximity + CHANGE_PRIORITY * change;
+
+            let current = priority
+                .values
+                .get(&receiver.0)
+                .copied()
+                .unwrap_or(BASE_PRIORITY);
+            if (value - current).abs() <= PRIORITY_EPSILON {
+                continue;
+            }
+            match server.update_priority(group, receiver.0, value) {
+                Ok(()) => {
+                    priority.values.insert(receiver.0, value);
+                }
+                // the receiver may be connecting or gone, try again next frame
+                Err(e) => debug!("Failed to update priority of {:?}: {:?}", entity, e),
+            }
+        }
+    }
+}
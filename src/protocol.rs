@@ -9,23 +9,38 @@ use lightyear::prelude::*;
 use lightyear::utils::bevy::*;
 use serde::{Deserialize, Serialize};
 
+use crate::collision::player_layers;
+
 #[derive(Bundle)]
 pub(crate) struct PlayerBundle {
     id: PlayerId,
+    profile: PlayerProfile,
     position: Position,
+    teleport_count: TeleportCount,
+    dash_cooldown: DashCooldown,
+    movement_state: MovementState,
     action_state: ActionState<PlayerActions>,
     physics: PhysicsBundle,
 }
 
 impl PlayerBundle {
-    pub(crate) fn new(id: ClientId, position: Vec3) -> Self {
+    pub(crate) fn collider() -> Collider {
+        Collider::cuboid(1.0, 1.0, 1.0)
+    }
+
+    pub(crate) fn new(id: ClientId, profile: PlayerProfile, position: Vec3) -> Self {
         Self {
             id: PlayerId(id),
+            profile,
             position: Position(position),
+            teleport_count: TeleportCount::default(),
+            dash_cooldown: DashCooldown::default(),
+            movement_state: MovementState::default(),
             action_state: ActionState::default(),
             physics: PhysicsBundle {
-                collider: Collider::cuboid(1.0, 1.0, 1.0),
+                collider: Self::collider(),
                 collider_density: ColliderDensity(3.0),
+                collision_layers: player_layers(),
                 rigid_body: RigidBody::Dynamic,
             },
         }
@@ -36,16 +51,77 @@ impl PlayerBundle {
 pub(crate) struct PhysicsBundle {
     pub(crate) collider: Collider,
     pub(crate) collider_density: ColliderDensity,
+    pub(crate) collision_layers: CollisionLayers,
     pub(crate) rigid_body: RigidBody,
 }
 
 #[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct PlayerId(pub ClientId);
 
+/// How a player shows up to others. Chosen in the main menu, sent in `ClientAssetsReady` and
+/// cleaned up by the server before it is replicated.
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerProfile {
+    pub name: String,
+    pub color: Color,
+    pub cosmetic: Cosmetic,
+}
+
+/// Something worn on top of the cube.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cosmetic {
+    #[default]
+    None,
+    Hat,
+    Antenna,
+}
+
+impl Cosmetic {
+    pub const ALL: [Cosmetic; 3] = [Cosmetic::None, Cosmetic::Hat, Cosmetic::Antenna];
+}
+
+/// Bumped by the server every time it moves the entity instantly, see `TeleportServerPlugin`.
+/// Clients snap to the new position instead of interpolating or smoothing towards it.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TeleportCount {
+    pub count: u32,
+    /// Server tick of the latest teleport.
+    pub tick: Tick,
+}
+
+impl Default for TeleportCount {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            tick: Tick(0),
+        }
+    }
+}
+
+/// Ticks left before the player can dash again. Predicted and rolled back like the rest of the
+/// movement, so the client knows exactly when the server lets it dash.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct DashCooldown {
+    pub remaining_ticks: u16,
+}
+
+/// What the player is in the middle of, kept next to `DashCooldown` so that a rollback restores
+/// it. Tnua's own action state is neither replicated nor rolled back, so movement is never
+/// decided from it.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct MovementState {
+    /// Ticks left in the current dash, 0 when not dashing.
+    pub dash_ticks: u16,
+    /// Crouch is held, or was and there's no room to stand up yet.
+    pub crouched: bool,
+}
+
 #[component_protocol(protocol = "MyProtocol")]
 pub enum Components {
     #[protocol(sync(mode = "once"))]
     PlayerId(PlayerId),
+    #[protocol(sync(mode = "simple"))]
+    PlayerProfile(PlayerProfile),
     // #[protocol(sync(
     //     mode = "full",
     //     lerp = "PositionLinearInterpolation",
@@ -66,38 +142,145 @@ pub enum Components {
     // GlobalTransform(GlobalTransform),
     #[protocol(sync(mode = "full", lerp = "TransformLinearInterpolation"))]
     Transform(Transform),
+    #[protocol(sync(mode = "simple"))]
+    TeleportCount(TeleportCount),
+    #[protocol(sync(mode = "full", lerp = "NullInterpolator"))]
+    DashCooldown(DashCooldown),
+    #[protocol(sync(mode = "full", lerp = "NullInterpolator"))]
+    MovementState(MovementState),
 }
 
 #[derive(Channel)]
 pub struct Channel1;
 
+/// Carries the level data during the loading handshake, kept apart so it doesn't hold up
+/// `Channel1`.
+#[derive(Channel)]
+pub struct LevelChannel;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ClientConnect {
     pub(crate) id: ClientId,
+    pub(crate) name: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ClientDisconnect {
     pub(crate) id: ClientId,
+    pub(crate) name: String,
+}
+
+/// First handshake stage, sent by the client as soon as it is connected.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClientHello {
+    pub(crate) game_version: String,
+    pub(crate) protocol_hash: u64,
+}
+
+/// The server accepted the `ClientHello`; the level follows on `LevelChannel`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HandshakeAccept;
+
+/// The server turned the client away. It disconnects the client shortly after.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HandshakeReject {
+    pub(crate) reason: String,
+}
+
+/// Last handshake stage: the client built the level it received, the server spawns the player.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClientAssetsReady {
+    /// `LevelDescription::content_hash` of the level the client built, glTF file included.
+    pub(crate) content_hash: u64,
+    /// What the player picked in the menu, the server may adjust it.
+    pub(crate) profile: PlayerProfile,
+    /// Watch the game without getting a player.
+    pub(crate) spectator: bool,
+}
+
+/// One piece of the serialized `LevelDescription`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LevelChunk {
+    pub(crate) index: u32,
+    pub(crate) total: u32,
+    pub(crate) bytes: Vec<u8>,
+}
+
+/// Broadcast by the server right before it disconnects everyone and exits.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ServerShutdown {
+    pub(crate) reason: String,
+}
+
+/// Tunable movement values. The server owns them, and clients must predict with the same ones.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct MovementSettings {
+    /// Walking speed with the stick pushed all the way.
+    pub walk_speed: f32,
+    pub jump_height: f32,
+    /// Height the character floats above the ground at.
+    pub float_height: f32,
+    /// Walk speed multipliers while sprinting and crouching.
+    pub sprint_multiplier: f32,
+    pub crouch_multiplier: f32,
+    /// Added to `float_height` while crouching.
+    pub crouch_float_offset: f32,
+    pub dash_distance: f32,
+    pub dash_speed: f32,
+    /// In fixed ticks, 64 per second.
+    pub dash_cooldown_ticks: u16,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            walk_speed: 1.0,
+            jump_height: 2.0,
+            float_height: 1.0,
+            sprint_multiplier: 2.0,
+            crouch_multiplier: 0.5,
+            crouch_float_offset: -0.4,
+            dash_distance: 4.0,
+            dash_speed: 20.0,
+            dash_cooldown_ticks: 64,
+        }
+    }
 }
 
+/// Sent by the server when a client is accepted and whenever its `MovementSettings` change.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-pub struct ClientAssetLoadingComplete;
+pub struct MovementSettingsUpdate {
+    pub(crate) settings: MovementSettings,
+    /// Server tick the settings apply from, far enough ahead that clients get them first.
+    pub(crate) since: Tick,
+}
 
 #[message_protocol(protocol = "MyProtocol")]
 pub enum Messages {
     ClientConnect(ClientConnect),
     ClientDisconnect(ClientDisconnect),
-    ClientAssetLoadingComplete(ClientAssetLoadingComplete),
+    ClientHello(ClientHello),
+    HandshakeAccept(HandshakeAccept),
+    HandshakeReject(HandshakeReject),
+    ClientAssetsReady(ClientAssetsReady),
+    ServerShutdown(ServerShutdown),
+    LevelChunk(LevelChunk),
+    MovementSettingsUpdate(MovementSettingsUpdate),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Hash, Reflect, Actionlike)]
 pub enum PlayerActions {
-    Up,
-    Down,
-    Left,
-    Right,
+    /// Walking direction, x to the right and y forward, with a length of at most 1.
+    Move,
     Jump,
+    Sprint,
+    Crouch,
+    /// Dashes the way the player is moving, or where the camera looks when standing still.
+    Dash,
+    /// Yaw of the camera in radians, set from the `FollowCamera` rather than bound to an input.
+    /// Movement is relative to it.
+    CameraYaw,
 }
 
 impl LeafwingUserAction for PlayerActions {}
@@ -111,12 +294,63 @@ protocolize! {
     LeafwingInput2 = NoAction2,
 }
 
+/// Compared during the handshake. Bump the package version with every release, builds of the
+/// same version are expected to play together.
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Bump whenever the fields of anything sent over the network change, including types declared
+/// outside this file such as `LevelDescription`, which travels inside `LevelChunk`s. What is
+/// sent and how is covered by `PROTOCOL_DESCRIPTION`.
+const PROTOCOL_REVISION: u32 = 1;
+
+/// Every message, component, input and channel with how it is sent. Keep it in sync with
+/// `Messages`, `Components`, `PlayerActions` and `protocol()`.
+const PROTOCOL_DESCRIPTION: &[&str] = &[
+    "message ClientConnect",
+    "message ClientDisconnect",
+    "message ClientHello",
+    "message HandshakeAccept",
+    "message HandshakeReject",
+    "message ClientAssetsReady",
+    "message ServerShutdown",
+    "message LevelChunk",
+    "message MovementSettingsUpdate",
+    "component PlayerId once",
+    "component PlayerProfile simple",
+    "component LinearVelocity full",
+    "component Transform full",
+    "component TeleportCount simple",
+    "component DashCooldown full",
+    "component MovementState full",
+    "input PlayerActions Move Jump Sprint Crouch Dash CameraYaw",
+    "channel Channel1 ordered-reliable",
+    "channel LevelChannel ordered-reliable",
+];
+
+/// Hash of the protocol, so that clients built against another one are turned away during the
+/// handshake instead of desyncing.
+pub fn protocol_hash() -> u64 {
+    let description = format!("{}|{}", PROTOCOL_REVISION, PROTOCOL_DESCRIPTION.join("|"));
+    content_hash(description.as_bytes())
+}
+
+/// FNV-1a, stable across builds and platforms unlike `DefaultHasher`.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 pub(crate) fn protocol() -> MyProtocol {
     let mut protocol = MyProtocol::default();
     protocol.add_channel::<Channel1>(ChannelSettings {
         mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
         ..default()
     });
+    protocol.add_channel::<LevelChannel>(ChannelSettings {
+        mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+        ..default()
+    });
     protocol
 }
 
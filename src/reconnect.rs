@@ -0,0 +1,215 @@
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use lightyear::client::events::MessageEvent;
+use lightyear::prelude::client::*;
+
+use crate::client::GameClientState;
+use crate::handshake::ASSETS_READY_TIMEOUT;
+use crate::menu::DisconnectReason;
+use crate::protocol::HandshakeAccept;
+
+/// How long we wait in `GameClientState::Connecting` for the server to accept us before giving
+/// up. Once accepted, we wait as long as the server gives us to load the level.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before the first reconnect attempt, doubled after every failed attempt.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Detects lost or never established connections, and keeps retrying with exponential backoff
+/// from `GameClientState::Disconnected`.
+pub struct ReconnectPlugin;
+
+impl Plugin for ReconnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReconnectBackoff>()
+            .add_systems(OnEnter(GameClientState::Connecting), start_connect_timeout)
+            .add_systems(OnEnter(GameClientState::Playing), reset_backoff)
+            .add_systems(
+                OnEnter(GameClientState::Disconnected),
+                (disconnect, schedule_reconnect, spawn_disconnected_screen),
+            )
+            .add_systems(
+                OnExit(GameClientState::Disconnected),
+                despawn_disconnected_screen,
+            )
+            .add_systems(
+                PreUpdate,
+                handle_connection_loss.after(MainSet::Receive).run_if(
+                    in_state(GameClientState::Connecting)
+                        .or_else(in_state(GameClientState::Playing)),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    check_connect_timeout.run_if(in_state(GameClientState::Connecting)),
+                    (wait_for_reconnect, back_to_menu, update_disconnected_screen)
+                        .run_if(in_state(GameClientState::Disconnected)),
+                ),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct ReconnectBackoff {
+    /// Failed attempts since we were last `Playing`.
+    attempts: u32,
+    timer: Timer,
+}
+
+impl ReconnectBackoff {
+    fn delay(&self) -> Duration {
+        RECONNECT_BASE_DELAY
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(RECONNECT_MAX_DELAY)
+    }
+}
+
+#[derive(Resource)]
+struct ConnectTimeout {
+    timer: Timer,
+    /// Shown when the timer runs out.
+    reason: &'static str,
+}
+
+#[derive(Component)]
+struct DisconnectedScreen;
+
+#[derive(Component)]
+struct DisconnectedText;
+
+fn start_connect_timeout(mut commands: Commands) {
+    commands.insert_resource(ConnectTimeout {
+        timer: Timer::new(CONNECT_TIMEOUT, TimerMode::Once),
+        reason: "no response from server",
+    });
+}
+
+fn reset_backoff(mut backoff: ResMut<ReconnectBackoff>, mut reason: ResMut<DisconnectReason>) {
+    backoff.attempts = 0;
+    reason.0 = None;
+}
+
+fn check_connect_timeout(
+    time: Res<Time>,
+    mut accepted: EventReader<MessageEvent<HandshakeAccept>>,
+    mut timeout: ResMut<ConnectTimeout>,
+    mut reason: ResMut<DisconnectReason>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    if accepted.read().last().is_some() {
+        // the server is there, the level can take a while to download and build
+        *timeout = ConnectTimeout {
+            timer: Timer::new(ASSETS_READY_TIMEOUT, TimerMode::Once),
+            reason: "timed out loading the level",
+        };
+    }
+
+    if timeout.timer.tick(time.delta()).just_finished() {
+        warn!("Timed out connecting to the server: {}", timeout.reason);
+        reason.0 = Some(timeout.reason.to_string());
+        next_state.set(GameClientState::Disconnected);
+    }
+}
+
+pub(crate) fn handle_connection_loss(
+    mut disconnections: EventReader<DisconnectEvent>,
+    mut reason: ResMut<DisconnectReason>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    if disconnections.read().last().is_some() {
+        warn!("Lost connection to the server");
+        reason.0 = Some("connection to server lost".to_string());
+        next_state.set(GameClientState::Disconnected);
+    }
+}
+
+fn disconnect(mut next_network_state: ResMut<NextState<NetworkingState>>) {
+    next_network_state.set(NetworkingState::Disconnected);
+}
+
+fn schedule_reconnect(mut backoff: ResMut<ReconnectBackoff>) {
+    let delay = backoff.delay();
+    info!(
+        "Reconnecting in {:?} (attempt {})",
+        delay,
+        backoff.attempts + 1
+    );
+    backoff.timer = Timer::new(delay, TimerMode::Once);
+}
+
+fn wait_for_reconnect(
+    time: Res<Time>,
+    mut backoff: ResMut<ReconnectBackoff>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    if backoff.timer.tick(time.delta()).just_finished() {
+        backoff.attempts += 1;
+        next_state.set(GameClientState::Connecting);
+    }
+}
+
+fn back_to_menu(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        next_state.set(GameClientState::MainMenu);
+    }
+}
+
+fn spawn_disconnected_screen(mut commands: Commands) {
+    commands.spawn((Camera2dBundle::default(), DisconnectedScreen));
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            DisconnectedScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 24.0,
+                        ..default()
+                    },
+                ),
+                DisconnectedText,
+            ));
+        });
+}
+
+fn update_disconnected_screen(
+    backoff: Res<ReconnectBackoff>,
+    reason: Res<DisconnectReason>,
+    mut text: Query<&mut Text, With<DisconnectedText>>,
+) {
+    let reason = reason.0.as_deref().unwrap_or("disconnected");
+    for mut text in text.iter_mut() {
+        text.sections[0].value = format!(
+            "{}\nreconnecting in {:.0}s (attempt {}), Esc for the main menu",
+            reason,
+            backoff.timer.remaining_secs().ceil(),
+            backoff.attempts + 1
+        );
+    }
+}
+
+fn despawn_disconnected_screen(
+    mut commands: Commands,
+    screen: Query<Entity, With<DisconnectedScreen>>,
+) {
+    for entity in screen.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use std::time::Duration;
+
+use crate::protocol::{PlayerBundle, PlayerId};
+use crate::server::AwaitingReconnect;
+use crate::spawn::SpawnPicker;
+use crate::teleport::{apply_teleports, Teleport};
+
+/// Where players are allowed to be. Leaving it, usually by falling off the level, respawns them.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct OutOfBounds {
+    /// Players below this height are out.
+    pub kill_height: f32,
+    /// Players further than this from the origin along X or Z are out. Defaults to the edge of
+    /// the arena's 128 by 128 ground.
+    pub half_extent: f32,
+    /// How long an out of bounds player stays frozen before respawning.
+    pub respawn_delay: Duration,
+}
+
+impl Default for OutOfBounds {
+    fn default() -> Self {
+        Self {
+            kill_height: -20.0,
+            half_extent: 64.0,
+            respawn_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl OutOfBounds {
+    fn contains(&self, position: Vec3) -> bool {
+        position.y < self.kill_height
+            || position.x.abs() > self.half_extent
+            || position.z.abs() > self.half_extent
+    }
+}
+
+/// A player that went out of bounds, frozen until the timer runs out.
+#[derive(Component)]
+pub(crate) struct Respawning(Timer);
+
+/// Server side: sends out of bounds players back to a spawn point.
+pub struct RespawnPlugin;
+
+impl Plugin for RespawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<OutOfBounds>().add_systems(
+            Update,
+            (detect_out_of_bounds, respawn_players)
+                .chain()
+                .before(apply_teleports),
+        );
+    }
+}
+
+fn detect_out_of_bounds(
+    out_of_bounds: Res<OutOfBounds>,
+    mut players: Query<
+        (
+            Entity,
+            &PlayerId,
+            &Position,
+            &mut RigidBody,
+            &mut LinearVelocity,
+        ),
+        (Without<Respawning>, Without<AwaitingReconnect>),
+    >,
+    mut commands: Commands,
+) {
+    for (entity, player_id, position, mut rigid_body, mut velocity) in players.iter_mut() {
+        if !out_of_bounds.contains(position.0) {
+            continue;
+        }
+
+        info!(
+            "Player {:?} of client {:?} is out of bounds at {:?}, respawning in {:?}",
+            entity, player_id.0, position.0, out_of_bounds.respawn_delay
+        );
+
+        // stop it there so it doesn't keep falling, and keep sending updates, meanwhile
+        *rigid_body = RigidBody::Static;
+        *velocity = LinearVelocity::ZERO;
+        commands.entity(entity).insert(Respawning(Timer::new(
+            out_of_bounds.respawn_delay,
+            TimerMode::Once,
+        )));
+    }
+}
+
+fn respawn_players(
+    time: Res<Time>,
+    mut players: Query<
+        (Entity, &PlayerId, &mut Respawning, &mut RigidBody),
+        Without<AwaitingReconnect>,
+    >,
+    mut spawn_picker: SpawnPicker,
+    mut teleports: EventWriter<Teleport>,
+    mut commands: Commands,
+) {
+    let mut taken = Vec::new();
+    for (entity, player_id, mut respawning, mut rigid_body) in players.iter_mut() {
+        if !respawning.0.tick(time.delta()).finished() {
+            continue;
+        }
+
+        let spawn_position = spawn_picker.pick(&PlayerBundle::collider(), &taken);
+        taken.push(spawn_position);
+
+        info!(
+            "Respawning player {:?} of client {:?} at {:?}",
+            entity, player_id.0, spawn_position
+        );
+
+        teleports.send(Teleport {
+            entity,
+            to: spawn_position,
+        });
+        *rigid_body = RigidBody::Dynamic;
+        commands.entity(entity).remove::<Respawning>();
+    }
+}
@@ -0,0 +1,132 @@
+use bevy::prelude::*;
+use lightyear::client::events::MessageEvent;
+use lightyear::prelude::client::MainSet;
+use lightyear::prelude::*;
+
+use crate::protocol::{ClientConnect, ClientDisconnect};
+
+/// How long a join/leave line stays in the feed.
+const FEED_ENTRY_LIFETIME_SECS: f32 = 5.0;
+
+/// Turns the server's join/leave broadcasts into events and shows them in a small feed.
+pub struct RosterPlugin;
+
+impl Plugin for RosterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlayerJoined>()
+            .add_event::<PlayerLeft>()
+            .add_systems(Startup, spawn_feed)
+            .add_systems(PreUpdate, receive_roster_changes.after(MainSet::Receive))
+            .add_systems(Update, (push_feed_entries, expire_feed_entries));
+    }
+}
+
+/// Another player finished loading and spawned into the game.
+#[derive(Event, Clone, Debug)]
+pub struct PlayerJoined {
+    pub id: ClientId,
+    pub name: String,
+}
+
+/// Another player left the game.
+#[derive(Event, Clone, Debug)]
+pub struct PlayerLeft {
+    pub id: ClientId,
+    pub name: String,
+}
+
+#[derive(Component)]
+struct Feed;
+
+#[derive(Component)]
+struct FeedEntry(Timer);
+
+fn receive_roster_changes(
+    mut connects: EventReader<MessageEvent<ClientConnect>>,
+    mut disconnects: EventReader<MessageEvent<ClientDisconnect>>,
+    mut joined: EventWriter<PlayerJoined>,
+    mut left: EventWriter<PlayerLeft>,
+) {
+    for event in connects.read() {
+        let message = event.message();
+        info!("Player joined: {:?} ({:?})", message.id, message.name);
+        joined.send(PlayerJoined {
+            id: message.id,
+            name: message.name.clone(),
+        });
+    }
+
+    for event in disconnects.read() {
+        let message = event.message();
+        info!("Player left: {:?} ({:?})", message.id, message.name);
+        left.send(PlayerLeft {
+            id: message.id,
+            name: message.name.clone(),
+        });
+    }
+}
+
+fn spawn_feed(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(12.0),
+                bottom: Val::Px(12.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            z_index: ZIndex::Global(10),
+            ..default()
+        },
+        Feed,
+    ));
+}
+
+fn push_feed_entries(
+    mut joined: EventReader<PlayerJoined>,
+    mut left: EventReader<PlayerLeft>,
+    feed: Query<Entity, With<Feed>>,
+    mut commands: Commands,
+) {
+    let Ok(feed) = feed.get_single() else {
+        return;
+    };
+
+    let lines = joined
+        .read()
+        .map(|event| format!("{} joined", event.name))
+        .chain(left.read().map(|event| format!("{} left", event.name)))
+        .collect::<Vec<_>>();
+
+    commands.entity(feed).with_children(|parent| {
+        for line in lines {
+            parent.spawn((
+                TextBundle::from_section(
+                    line,
+                    TextStyle {
+                        font_size: 18.0,
+                        ..default()
+                    },
+                ),
+                FeedEntry(Timer::from_seconds(
+                    FEED_ENTRY_LIFETIME_SECS,
+                    TimerMode::Once,
+                )),
+            ));
+        }
+    });
+}
+
+fn expire_feed_entries(
+    time: Res<Time>,
+    mut entries: Query<(Entity, &mut FeedEntry)>,
+    mut commands: Commands,
+) {
+    for (entity, mut entry) in entries.iter_mut() {
+        if entry.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -8,38 +8,70 @@ use bevy::render::settings::WgpuSettings;
 use bevy::render::RenderPlugin;
 use bevy::winit::WinitPlugin;
 use bevy_tnua::control_helpers::TnuaCrouchEnforcerPlugin;
-use bevy_tnua::controller::{TnuaController, TnuaControllerBundle, TnuaControllerPlugin};
+use bevy_tnua::controller::{TnuaControllerBundle, TnuaControllerPlugin};
 use bevy_tnua::TnuaUserControlsSystemSet;
 use bevy_tnua_xpbd3d::{TnuaXpbd3dPlugin, TnuaXpbd3dSensorShape};
-use bevy_xpbd_3d::components::{LinearVelocity, LockedAxes, Position, RigidBody};
+use bevy_xpbd_3d::components::{LinearVelocity, LockedAxes, RigidBody};
 use bevy_xpbd_3d::plugins::collision::Collider;
 use bevy_xpbd_3d::plugins::setup::Physics;
 use bevy_xpbd_3d::plugins::PhysicsPlugins;
 use leafwing_input_manager::action_state::ActionState;
+use lightyear::prelude::*;
 use lightyear::server::input_leafwing::LeafwingInputPlugin;
 use lightyear::shared::config::Mode;
-use lightyear::{prelude::*, server::events::MessageEvent};
 
 use async_compat::Compat;
 use bevy::tasks::IoTaskPool;
 use bevy::tasks::TaskPool;
 use lightyear::prelude::server::Certificate;
 
-use std::collections::HashMap;
+use bevy::app::AppExit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::movement::shared_movement_behaviour;
+use crate::handshake::{ClientReady, HandshakePlugin};
+use crate::level::{spawn_level, LevelDescription, LevelPlugin, LevelReady, LevelSet};
+use crate::ownership::{despawn_owned_entities, KeepOnDisconnect, OwnedBy, OwnershipPlugin};
+use crate::priority::PriorityPlugin;
 use crate::protocol::{
-    protocol, ClientAssetLoadingComplete, MyProtocol, PlayerActions, PlayerBundle, Replicate,
+    protocol, Channel1, ClientConnect, ClientDisconnect, MyProtocol, PlayerActions, PlayerBundle,
+    PlayerId, PlayerProfile, Replicate, ServerConnectionManager, ServerShutdown,
 };
-use crate::shared::{shared_config, SharedPlugin, FIXED_TIMESTEP_HZ};
+use crate::respawn::{RespawnPlugin, Respawning};
+use crate::shared::{server_packet_config, shared_config, SharedPlugin, FIXED_TIMESTEP_HZ};
+use crate::spawn::{SpawnPicker, SpawnPlugin};
+use crate::teleport::TeleportServerPlugin;
+use crate::tuning::TuningServerPlugin;
+
+/// How long the server keeps running after broadcasting `ServerShutdown`, so the message
+/// goes out with the next sends before the connections are closed.
+const SHUTDOWN_FLUSH_DELAY: Duration = Duration::from_millis(250);
+
+/// Raised by the SIGINT/SIGTERM handler.
+#[derive(Resource, Clone, Default)]
+pub(crate) struct ShutdownSignal(Arc<AtomicBool>);
 
 #[derive(Resource)]
-pub(crate) struct ServerGlobal {
-    pub client_id_to_entity_id: HashMap<ClientId, Entity>,
+pub(crate) struct ShuttingDown(Timer);
+
+/// How long a disconnected player's entity stays in the world, frozen, waiting for the same
+/// client id to come back.
+#[derive(Resource)]
+pub(crate) struct ReconnectGracePeriod(pub Duration);
+
+impl Default for ReconnectGracePeriod {
+    fn default() -> Self {
+        Self(Duration::from_secs(30))
+    }
 }
 
-pub fn server_app(net_config: server::NetConfig) -> App {
+/// A player whose client dropped. Despawned once the timer runs out.
+#[derive(Component)]
+pub(crate) struct AwaitingReconnect(Timer);
+
+/// `send_budget_bytes` caps what each client is sent every `SERVER_SEND_INTERVAL`.
+pub fn server_app(net_config: server::NetConfig, send_budget_bytes: u32) -> App {
     let mut app = App::new();
 
     app.add_plugins(
@@ -66,6 +98,7 @@ pub fn server_app(net_config: server::NetConfig) -> App {
     let server_config = server::ServerConfig {
         shared: shared_config(Mode::Separate),
         net: vec![net_config],
+        packet: server_packet_config(send_budget_bytes),
         // replication: server::ReplicationConfig {
         //     // enable send because we pre-spawn entities on the client
         //     enable_send: true,
@@ -78,17 +111,38 @@ pub fn server_app(net_config: server::NetConfig) -> App {
 
     app.add_plugins(lightyear::prelude::server::ServerPlugin::new(plugin_config));
 
-    app.insert_resource(ServerGlobal {
-        client_id_to_entity_id: Default::default(),
-    });
+    let shutdown_signal = ShutdownSignal::default();
+    let handler_signal = shutdown_signal.clone();
+    ctrlc::set_handler(move || handler_signal.0.store(true, Ordering::SeqCst))
+        .expect("Failed to set shutdown signal handler");
+    app.insert_resource(shutdown_signal);
+
+    app.init_resource::<ReconnectGracePeriod>();
+    app.init_resource::<LevelDescription>();
+
     app.add_systems(Startup, init);
     app.add_systems(
         Update,
-        (handle_connections, on_client_asset_loading_complete),
+        (
+            handle_connections.before(despawn_owned_entities),
+            expire_reconnect_grace_period,
+            on_client_ready,
+            start_server_when_level_ready.after(LevelSet),
+            handle_shutdown_signal.run_if(not(resource_exists::<ShuttingDown>)),
+            finish_shutdown.run_if(resource_exists::<ShuttingDown>),
+        ),
     )
     .add_plugins(LeafwingInputPlugin::<MyProtocol, PlayerActions>::default());
 
     app.add_plugins(SharedPlugin);
+    app.add_plugins(PriorityPlugin);
+    app.add_plugins(OwnershipPlugin);
+    app.add_plugins(HandshakePlugin);
+    app.add_plugins(LevelPlugin { headless: true });
+    app.add_plugins(SpawnPlugin);
+    app.add_plugins(RespawnPlugin);
+    app.add_plugins(TeleportServerPlugin);
+    app.add_plugins(TuningServerPlugin);
 
     app
 }
@@ -129,105 +183,270 @@ pub fn build_server_net_config() -> lightyear::prelude::server::NetConfig {
     }
 }
 
-fn movement(
-    tick_manager: Res<TickManager>,
-    mut action_query: Query<(
-        Entity,
-        &Position,
-        &mut TnuaController,
-        &ActionState<PlayerActions>,
-    )>,
-) {
-    for (entity, position, mut controller, action_state) in action_query.iter_mut() {
-        // NOTE: be careful to directly pass Mut<PlayerPosition>
-        // getting a mutable reference triggers change detection, unless you use `as_deref_mut()`
-        // shared_movement_behaviour(velocity, action);
-        info!(?entity, tick = ?tick_manager.tick(), ?position, actions = ?action_state.get_pressed(), "applying movement to player");
-
-        shared_movement_behaviour(&mut controller, action_state);
-    }
+fn init(mut commands: Commands, asset_server: Res<AssetServer>, level: Res<LevelDescription>) {
+    spawn_level(&mut commands, &asset_server, &level);
 }
 
-fn init(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
+/// Players fall through the world if they join before it exists, so only start listening
+/// once the level is in place.
+fn start_server_when_level_ready(
+    mut ready: EventReader<LevelReady>,
     mut connections: ResMut<lightyear::prelude::server::ServerConnections>,
 ) {
-    connections.start().expect("Failed to start server");
-
-    // Spawn the ground.
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Plane3d::default().mesh().size(128.0, 128.0)),
-            transform: Transform::from_xyz(0.0, 0.0, 0.0),
-            ..Default::default()
-        },
-        RigidBody::Static,
-        Collider::halfspace(Vec3::Y),
-    ));
+    if ready.read().last().is_some() {
+        connections.start().expect("Failed to start server");
+    }
 }
 
 fn handle_connections(
     // mut connections: EventReader<lightyear::prelude::server::ConnectEvent>,
     mut disconnections: EventReader<lightyear::prelude::server::DisconnectEvent>,
-    mut global: ResMut<ServerGlobal>,
+    grace_period: Res<ReconnectGracePeriod>,
+    mut players: Query<
+        (
+            Entity,
+            &PlayerId,
+            &mut RigidBody,
+            &mut LinearVelocity,
+            &mut ActionState<PlayerActions>,
+        ),
+        With<KeepOnDisconnect>,
+    >,
     mut commands: Commands,
 ) {
+    // the level is sent as part of the handshake, see `HandshakePlugin`
     for disconnection in disconnections.read() {
         let client_id = disconnection.context();
-        // TODO: handle this automatically in lightyear
-        //  - provide a Owned component in lightyear that can specify that an entity is owned by a specific player?
-        //  - maybe have the client-id to entity-mapping in the global metadata?
-        //  - despawn automatically those entities when the client disconnects
-        if let Some(entity) = global.client_id_to_entity_id.remove(client_id) {
-            if let Some(mut entity) = commands.get_entity(entity) {
-                entity.despawn();
+        // everything else the client owns is despawned right away, see `despawn_owned_entities`
+        for (entity, player_id, mut rigid_body, mut velocity, mut action_state) in
+            players.iter_mut()
+        {
+            if player_id.0 != *client_id {
+                continue;
             }
+
+            info!(
+                "Freezing player {:?} of disconnected client {:?} for {:?}",
+                entity, client_id, grace_period.0
+            );
+
+            *rigid_body = RigidBody::Static;
+            *velocity = LinearVelocity::ZERO;
+            *action_state = ActionState::default();
+            commands.entity(entity).insert((
+                AwaitingReconnect(Timer::new(grace_period.0, TimerMode::Once)),
+                frozen_replicate(*client_id),
+            ));
         }
     }
 }
 
-fn on_client_asset_loading_complete(
-    mut reader: EventReader<MessageEvent<ClientAssetLoadingComplete>>,
-    mut global: ResMut<ServerGlobal>,
+fn expire_reconnect_grace_period(
+    time: Res<Time>,
+    mut waiting: Query<(Entity, &PlayerId, &PlayerProfile, &mut AwaitingReconnect)>,
+    mut server: ResMut<ServerConnectionManager>,
     mut commands: Commands,
 ) {
-    for event in reader.read() {
-        let client_id = *event.context();
+    for (entity, player_id, profile, mut awaiting) in waiting.iter_mut() {
+        if !awaiting.0.tick(time.delta()).just_finished() {
+            continue;
+        }
 
         info!(
-            "Received ClientLoadingCompleteRequest message: {:?}, {:?}",
-            event.message(),
-            client_id
+            "Client {:?} did not come back, despawning its player",
+            player_id.0
         );
+        commands.entity(entity).despawn_recursive();
+
+        // other clients still saw the frozen player, so it only leaves for them now
+        server
+            .send_message_to_target::<Channel1, ClientDisconnect>(
+                ClientDisconnect {
+                    id: player_id.0,
+                    name: profile.name.clone(),
+                },
+                NetworkTarget::AllExceptSingle(player_id.0),
+            )
+            .unwrap_or_else(|e| {
+                error!("Failed to send message: {:?}", e);
+            });
+    }
+}
+
+fn player_replicate(client_id: ClientId) -> Replicate {
+    let mut replicate = Replicate {
+        prediction_target: NetworkTarget::Single(client_id),
+        replicate_hierarchy: false,
+        interpolation_target: NetworkTarget::AllExceptSingle(client_id),
+        ..default()
+    };
 
-        let mut replicate = Replicate {
-            prediction_target: NetworkTarget::Single(client_id),
-            replicate_hierarchy: false,
-            interpolation_target: NetworkTarget::AllExceptSingle(client_id),
-            ..default()
-        };
+    replicate.add_target::<ActionState<PlayerActions>>(NetworkTarget::AllExceptSingle(client_id));
 
-        replicate
-            .add_target::<ActionState<PlayerActions>>(NetworkTarget::AllExceptSingle(client_id));
+    replicate.add_target::<LinearVelocity>(NetworkTarget::Single(client_id));
 
-        replicate.add_target::<LinearVelocity>(NetworkTarget::Single(client_id));
+    replicate
+}
+
+/// Replication of a player whose client dropped. The client is no longer a target, so that it
+/// isn't sent the player again before it is done with the handshake; `player_replicate` adds it
+/// back once it is, and lightyear spawns the entity on targets it gains.
+fn frozen_replicate(client_id: ClientId) -> Replicate {
+    let mut replicate = Replicate {
+        replication_target: NetworkTarget::AllExceptSingle(client_id),
+        prediction_target: NetworkTarget::None,
+        replicate_hierarchy: false,
+        interpolation_target: NetworkTarget::AllExceptSingle(client_id),
+        ..default()
+    };
+
+    replicate.add_target::<ActionState<PlayerActions>>(NetworkTarget::AllExceptSingle(client_id));
+
+    replicate.add_target::<LinearVelocity>(NetworkTarget::None);
+
+    replicate
+}
 
-        let entity = commands.spawn((
-            PlayerBundle::new(client_id, Vec3::new(0.0, 10.0, 0.0)),
-            replicate,
+fn spawn_player(
+    commands: &mut Commands,
+    client_id: ClientId,
+    profile: PlayerProfile,
+    position: Vec3,
+) -> Entity {
+    commands
+        .spawn((
+            PlayerBundle::new(client_id, profile, position),
+            OwnedBy(client_id),
+            KeepOnDisconnect,
+            player_replicate(client_id),
             TnuaControllerBundle::default(),
             // LockedAxes::new().lock_rotation_x().lock_rotation_z(),
             LockedAxes::ROTATION_LOCKED,
             TnuaXpbd3dSensorShape(Collider::cuboid(0.98, 0.98, 0.98)),
             SpatialBundle {
-                transform: Transform::from_xyz(0.0, 10.0, 0.0),
+                transform: Transform::from_translation(position),
                 ..default()
             },
-        ));
+        ))
+        .id()
+}
+
+fn on_client_ready(
+    mut reader: EventReader<ClientReady>,
+    mut waiting: Query<
+        (Entity, &PlayerId, &mut RigidBody, Has<Respawning>),
+        With<AwaitingReconnect>,
+    >,
+    mut spawn_picker: SpawnPicker,
+    mut server: ResMut<ServerConnectionManager>,
+    mut commands: Commands,
+) {
+    let mut taken = Vec::new();
+    for event in reader.read() {
+        let client_id = event.client_id;
+
+        if event.spectator {
+            // a player left behind by an earlier connection still expires as usual
+            info!(
+                "Client {:?} ({:?}) joined as a spectator",
+                client_id, event.profile.name
+            );
+            continue;
+        }
+
+        if let Some((entity, _, mut rigid_body, respawning)) = waiting
+            .iter_mut()
+            .find(|(_, player_id, _, _)| player_id.0 == client_id)
+        {
+            info!(
+                "Re-binding client {:?} to its player entity {:?}",
+                client_id, entity
+            );
+
+            // a player that fell out right before disconnecting stays frozen until
+            // `respawn_players` is done with it
+            if !respawning {
+                *rigid_body = RigidBody::Dynamic;
+            }
+            // the client is a replication target again, so lightyear spawns the entity on it,
+            // `Predicted` like on the first join, see `frozen_replicate`
+            commands
+                .entity(entity)
+                .remove::<AwaitingReconnect>()
+                .insert((event.profile.clone(), player_replicate(client_id)));
+            continue;
+        }
+
+        let spawn_position = spawn_picker.pick(&PlayerBundle::collider(), &taken);
+        taken.push(spawn_position);
+
+        let entity = spawn_player(
+            &mut commands,
+            client_id,
+            event.profile.clone(),
+            spawn_position,
+        );
+
+        info!(
+            "Create entity {:?} for client {:?} ({:?}) at {:?}",
+            entity, client_id, event.profile.name, spawn_position
+        );
 
-        // Add a mapping from client id to entity id
-        global.client_id_to_entity_id.insert(client_id, entity.id());
-        info!("Create entity {:?} for client {:?}", entity.id(), client_id);
+        server
+            .send_message_to_target::<Channel1, ClientConnect>(
+                ClientConnect {
+                    id: client_id,
+                    name: event.profile.name.clone(),
+                },
+                NetworkTarget::AllExceptSingle(client_id),
+            )
+            .unwrap_or_else(|e| {
+                error!("Failed to send message: {:?}", e);
+            });
     }
 }
+
+fn handle_shutdown_signal(
+    signal: Res<ShutdownSignal>,
+    mut server: ResMut<ServerConnectionManager>,
+    mut commands: Commands,
+) {
+    if !signal.0.load(Ordering::SeqCst) {
+        return;
+    }
+
+    info!("Shutdown requested, notifying clients");
+
+    server
+        .send_message_to_target::<Channel1, ServerShutdown>(
+            ServerShutdown {
+                reason: "server is shutting down".to_string(),
+            },
+            NetworkTarget::All,
+        )
+        .unwrap_or_else(|e| {
+            error!("Failed to send message: {:?}", e);
+        });
+
+    commands.insert_resource(ShuttingDown(Timer::new(
+        SHUTDOWN_FLUSH_DELAY,
+        TimerMode::Once,
+    )));
+}
+
+fn finish_shutdown(
+    time: Res<Time>,
+    mut shutting_down: ResMut<ShuttingDown>,
+    mut connections: ResMut<lightyear::prelude::server::ServerConnections>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if !shutting_down.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    info!("Disconnecting all clients");
+    connections.stop().unwrap_or_else(|e| {
+        error!("Failed to stop server: {:?}", e);
+    });
+    exit.send(AppExit);
+}
@@ -0,0 +1,103 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use bevy::log::{info, warn};
+use bevy::prelude::{Color, Resource};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{Cosmetic, PlayerProfile};
+
+/// Directory name under the user's config directory.
+const APP_DIR: &str = "networked_cube_test";
+
+pub(crate) const CLIENT_SETTINGS_FILE: &str = "client.ron";
+pub(crate) const CONTROLS_SETTINGS_FILE: &str = "controls.ron";
+pub(crate) const MOVEMENT_SETTINGS_FILE: &str = "movement.ron";
+
+/// How many servers the main menu remembers.
+const MAX_RECENT_SERVERS: usize = 5;
+
+/// What the main menu remembers between runs.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub(crate) struct ClientSettings {
+    pub(crate) player_name: String,
+    pub(crate) player_color: Color,
+    pub(crate) cosmetic: Cosmetic,
+    /// Most recent first.
+    pub(crate) recent_servers: Vec<String>,
+}
+
+impl ClientSettings {
+    /// Sent to the server when joining, which may still change the name.
+    pub(crate) fn profile(&self) -> PlayerProfile {
+        PlayerProfile {
+            name: self.player_name.clone(),
+            color: self.player_color,
+            cosmetic: self.cosmetic,
+        }
+    }
+
+    pub(crate) fn remember_server(&mut self, server_addr: &str) {
+        self.recent_servers.retain(|recent| recent != server_addr);
+        self.recent_servers.insert(0, server_addr.to_string());
+        self.recent_servers.truncate(MAX_RECENT_SERVERS);
+    }
+}
+
+fn config_path(file: &str) -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR).join(file))
+}
+
+/// Loads `file` from the user config directory, falling back to the default when it is missing
+/// or can't be parsed.
+pub(crate) fn load<T: DeserializeOwned + Default>(file: &str) -> T {
+    try_load(file).unwrap_or_default()
+}
+
+/// Like `load`, but gives nothing instead of the default.
+pub(crate) fn try_load<T: DeserializeOwned>(file: &str) -> Option<T> {
+    let path = config_path(file)?;
+    let contents = fs::read_to_string(&path).ok()?;
+
+    ron::from_str(&contents)
+        .map_err(|e| warn!("Ignoring corrupt settings file {:?}: {}", path, e))
+        .ok()
+}
+
+/// When `file` was last written to, if it exists.
+pub(crate) fn modified(file: &str) -> Option<SystemTime> {
+    let path = config_path(file)?;
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+pub(crate) fn save<T: Serialize>(file: &str, value: &T) {
+    let Some(path) = config_path(file) else {
+        warn!("No config directory, not saving {}", file);
+        return;
+    };
+
+    let contents = match ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default()) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("Failed to serialize {}: {}", file, e);
+            return;
+        }
+    };
+
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            warn!("Failed to create {:?}: {}", dir, e);
+            return;
+        }
+    }
+
+    match fs::write(&path, contents) {
+        Ok(()) => info!("Saved {:?}", path),
+        Err(e) => warn!("Failed to save {:?}: {}", path, e),
+    }
+}
@@ -13,15 +13,22 @@ use bevy_tnua_xpbd3d::TnuaXpbd3dPlugin;
 use leafwing_input_manager::action_state::ActionState;
 use lightyear::prelude::*;
 use lightyear::prelude::client::{Confirmed, Rollback, RollbackState};
-use crate::movement::shared_movement_behaviour;
+use crate::movement::{headroom_blocked, shared_movement_behaviour};
 use crate::protocol::*;
+use crate::tuning::{simulated_tick, MovementSettingsHistory};
 
 pub const FIXED_TIMESTEP_HZ: f64 = 64.0;
 
+pub const SERVER_SEND_INTERVAL: Duration = Duration::from_millis(40);
+
+/// Default for the maximum number of bytes the server sends to a single client every
+/// `SERVER_SEND_INTERVAL`, see `server_packet_config`.
+pub const DEFAULT_SERVER_SEND_BUDGET_BYTES: u32 = 1200;
+
 pub fn shared_config(mode: Mode) -> SharedConfig {
     SharedConfig {
         client_send_interval: Duration::default(),
-        server_send_interval: Duration::from_millis(40),
+        server_send_interval: SERVER_SEND_INTERVAL,
         tick: TickConfig {
             tick_duration: Duration::from_secs_f64(1.0 / FIXED_TIMESTEP_HZ),
         },
@@ -29,6 +36,17 @@ pub fn shared_config(mode: Mode) -> SharedConfig {
     }
 }
 
+/// Per-connection bandwidth cap allowing `send_budget_bytes` every `SERVER_SEND_INTERVAL`.
+/// Updates that don't fit are deferred to a later send, not dropped.
+pub fn server_packet_config(send_budget_bytes: u32) -> PacketConfig {
+    let sends_per_second = 1.0 / SERVER_SEND_INTERVAL.as_secs_f64();
+    PacketConfig::default()
+        .enable_bandwidth_cap()
+        .with_send_bandwidth_bytes_per_second_cap(
+            (send_budget_bytes as f64 * sends_per_second) as u32,
+        )
+}
+
 pub struct SharedPlugin;
 
 impl Plugin for SharedPlugin {
@@ -91,19 +109,32 @@ pub(crate) fn last_log(
 
 fn movement(
     tick_manager: Res<TickManager>,
+    rollback: Option<Res<Rollback>>,
+    movement_settings: Res<MovementSettingsHistory>,
+    spatial_query: SpatialQuery,
     mut action_query: Query<
         (
             Entity,
             &Position,
             &mut TnuaController,
+            &mut MovementState,
+            &mut DashCooldown,
             &ActionState<PlayerActions>,
         ),
         Or<(With<LocalPlayer>, With<Replicate>)>,
     >,
 ) {
-    for (entity, position, mut controller, action_state) in action_query.iter_mut() {
+    for (entity, position, mut controller, mut state, mut dash_cooldown, action_state) in action_query.iter_mut() {
         info!(?entity, tick = ?tick_manager.tick(), ?position, actions = ?action_state.get_pressed(), "applying movement to player");
 
-        shared_movement_behaviour(&mut controller, action_state);
+        let settings = movement_settings.at(simulated_tick(&tick_manager, rollback.as_deref()));
+        shared_movement_behaviour(
+            &mut controller,
+            &mut state,
+            &mut dash_cooldown,
+            || headroom_blocked(&spatial_query, entity, position.0, settings),
+            action_state,
+            settings,
+        );
     }
 }
\ No newline at end of file
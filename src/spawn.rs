@@ -0,0 +1,119 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+
+use crate::level::SpawnPoint;
+use crate::protocol::PlayerId;
+
+/// Used when the level has no spawn points.
+const FALLBACK_SPAWN: Vec3 = Vec3::new(0.0, 10.0, 0.0);
+/// When every spawn point is occupied, we look for room this much higher, a few times.
+const STACK_HEIGHT: f32 = 2.0;
+const STACK_ATTEMPTS: usize = 8;
+
+/// How the server picks among the level's spawn points.
+#[derive(Resource, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpawnStrategy {
+    /// Each spawn point in turn.
+    #[default]
+    RoundRobin,
+    Random,
+    /// The spawn point furthest away from every player.
+    FarthestFromPlayers,
+}
+
+#[derive(Resource, Default)]
+struct RoundRobinCursor(usize);
+
+pub struct SpawnPlugin;
+
+impl Plugin for SpawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpawnStrategy>()
+            .init_resource::<RoundRobinCursor>();
+    }
+}
+
+/// Picks where to put a player, avoiding spots already taken by a collider.
+#[derive(SystemParam)]
+pub struct SpawnPicker<'w, 's> {
+    strategy: Res<'w, SpawnStrategy>,
+    cursor: ResMut<'w, RoundRobinCursor>,
+    spawn_points: Query<'w, 's, &'static GlobalTransform, With<SpawnPoint>>,
+    players: Query<'w, 's, &'static Position, With<PlayerId>>,
+    spatial_query: SpatialQuery<'w, 's>,
+}
+
+impl SpawnPicker<'_, '_> {
+    /// `shape` is the collider of the player about to be spawned. `taken` holds positions
+    /// handed out earlier in the same frame, which physics doesn't know about yet.
+    pub fn pick(&mut self, shape: &Collider, taken: &[Vec3]) -> Vec3 {
+        let candidates = self.candidates();
+        let Some(first) = candidates.first().copied() else {
+            return FALLBACK_SPAWN;
+        };
+
+        (0..STACK_ATTEMPTS)
+            .flat_map(|level| {
+                candidates
+                    .iter()
+                    .map(move |candidate| *candidate + Vec3::Y * STACK_HEIGHT * level as f32)
+            })
+            .find(|candidate| self.is_free(shape, *candidate, taken))
+            .unwrap_or_else(|| {
+                warn!("No free spawn point, spawning on top of {:?}", first);
+                first
+            })
+    }
+
+    /// Spawn point positions, most preferred first.
+    fn candidates(&mut self) -> Vec<Vec3> {
+        let mut candidates = self
+            .spawn_points
+            .iter()
+            .map(|transform| transform.translation())
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return candidates;
+        }
+
+        match *self.strategy {
+            SpawnStrategy::RoundRobin => {
+                let start = self.cursor.0 % candidates.len();
+                self.cursor.0 = start + 1;
+                candidates.rotate_left(start);
+            }
+            SpawnStrategy::Random => candidates.shuffle(&mut rand::thread_rng()),
+            SpawnStrategy::FarthestFromPlayers => {
+                let nearest_player = |candidate: &Vec3| {
+                    self.players
+                        .iter()
+                        .map(|position| position.distance(*candidate))
+                        .fold(f32::INFINITY, f32::min)
+                };
+                candidates.sort_by(|a, b| nearest_player(b).total_cmp(&nearest_player(a)));
+            }
+        }
+
+        candidates
+    }
+
+    fn is_free(&self, shape: &Collider, candidate: Vec3, taken: &[Vec3]) -> bool {
+        let clear_of_taken = taken
+            .iter()
+            .all(|taken| taken.distance(candidate) > STACK_HEIGHT);
+
+        clear_of_taken
+            && self
+                .spatial_query
+                .shape_intersections(
+                    shape,
+                    candidate,
+                    Quat::IDENTITY,
+                    SpatialQueryFilter::default(),
+                )
+                .is_empty()
+    }
+}
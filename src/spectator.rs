@@ -0,0 +1,153 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use lightyear::prelude::client::*;
+
+use crate::camera::FollowCamera;
+use crate::client::{spawn_player_camera, GameClientState};
+use crate::protocol::PlayerId;
+
+/// Free-fly speed in units per second, multiplied by `FAST_FLY_MULTIPLIER` while Shift is held.
+const FLY_SPEED: f32 = 12.0;
+const FAST_FLY_MULTIPLIER: f32 = 3.0;
+/// Radians per pixel of mouse movement, while the right mouse button is held.
+const LOOK_SENSITIVITY: f32 = 0.005;
+/// Switches between following each player in turn and flying freely.
+const CYCLE_KEY: KeyCode = KeyCode::Tab;
+
+/// Connects without getting a player: the server skips the spawn, and we watch with a free-fly
+/// camera that can also follow any player.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct SpectatorMode(pub bool);
+
+/// Free-fly look angles, used while the camera isn't following anyone.
+#[derive(Component, Debug)]
+struct SpectatorCamera {
+    yaw: f32,
+    pitch: f32,
+}
+
+pub struct SpectatorPlugin;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpectatorMode>()
+            .add_systems(
+                OnEnter(GameClientState::Playing),
+                spawn_spectator_camera.run_if(spectating),
+            )
+            .add_systems(
+                Update,
+                (cycle_followed_player, fly_camera)
+                    .chain()
+                    .run_if(in_state(GameClientState::Playing))
+                    .run_if(spectating),
+            );
+    }
+}
+
+fn spectating(mode: Res<SpectatorMode>) -> bool {
+    mode.0
+}
+
+fn spawn_spectator_camera(mut commands: Commands) {
+    let camera = spawn_player_camera(&mut commands);
+    let pitch = -0.5;
+    commands.entity(camera).insert((
+        SpectatorCamera { yaw: 0.0, pitch },
+        Transform::from_xyz(0.0, 15.0, 25.0).with_rotation(Quat::from_rotation_x(pitch)),
+    ));
+}
+
+/// Follows the next player, or goes back to flying freely after the last one.
+fn cycle_followed_player(
+    keys: Res<ButtonInput<KeyCode>>,
+    players: Query<Entity, (With<PlayerId>, With<Interpolated>)>,
+    mut cameras: Query<(Entity, &mut SpectatorCamera, Option<&FollowCamera>)>,
+    mut commands: Commands,
+) {
+    let cycle = keys.just_pressed(CYCLE_KEY);
+
+    for (entity, mut spectator, follow) in cameras.iter_mut() {
+        let followed = follow.and_then(|follow| follow.target);
+        // the player we followed left, fly from where we are
+        let lost = followed.is_some_and(|followed| !players.contains(followed));
+        if !cycle && !lost {
+            continue;
+        }
+
+        let next = if lost {
+            None
+        } else {
+            let mut candidates = players.iter().collect::<Vec<_>>();
+            candidates.sort();
+            match followed {
+                Some(followed) => candidates
+                    .iter()
+                    .skip_while(|candidate| **candidate != followed)
+                    .nth(1)
+                    .copied(),
+                None => candidates.first().copied(),
+            }
+        };
+
+        match next {
+            Some(target) => {
+                info!("Spectating player {:?}", target);
+                commands
+                    .entity(entity)
+                    .insert(FollowCamera::following(target));
+            }
+            None => {
+                info!("Spectating in free-fly");
+                if let Some(follow) = follow {
+                    spectator.yaw = follow.yaw;
+                    spectator.pitch = follow.pitch;
+                }
+                commands.entity(entity).remove::<FollowCamera>();
+            }
+        }
+    }
+}
+
+fn fly_camera(
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut cameras: Query<(&mut SpectatorCamera, &mut Transform), Without<FollowCamera>>,
+) {
+    let look = if mouse_buttons.pressed(MouseButton::Right) {
+        mouse_motion.read().map(|motion| motion.delta).sum::<Vec2>() * LOOK_SENSITIVITY
+    } else {
+        mouse_motion.clear();
+        Vec2::ZERO
+    };
+
+    let mut direction = Vec3::ZERO;
+    for (key, axis) in [
+        (KeyCode::KeyW, Vec3::NEG_Z),
+        (KeyCode::KeyS, Vec3::Z),
+        (KeyCode::KeyA, Vec3::NEG_X),
+        (KeyCode::KeyD, Vec3::X),
+        (KeyCode::KeyE, Vec3::Y),
+        (KeyCode::KeyQ, Vec3::NEG_Y),
+    ] {
+        if keys.pressed(key) {
+            direction += axis;
+        }
+    }
+    let speed = if keys.pressed(KeyCode::ShiftLeft) {
+        FLY_SPEED * FAST_FLY_MULTIPLIER
+    } else {
+        FLY_SPEED
+    };
+
+    for (mut spectator, mut transform) in cameras.iter_mut() {
+        spectator.yaw -= look.x;
+        spectator.pitch = (spectator.pitch - look.y).clamp(-1.5, 1.5);
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, spectator.yaw, spectator.pitch, 0.0);
+
+        let movement = transform.rotation * direction.normalize_or_zero();
+        transform.translation += movement * speed * time.delta_seconds();
+    }
+}
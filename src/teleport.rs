@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use lightyear::client::interpolation::InterpolateStatus;
+use lightyear::prelude::client::{Interpolated, InterpolationSet};
+use lightyear::prelude::*;
+
+use crate::protocol::TeleportCount;
+
+/// Moves an entity instantly, e.g. on respawn, admin teleport or map change. Clients snap to
+/// the new position rather than interpolating or smoothing towards it.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct Teleport {
+    pub entity: Entity,
+    pub to: Vec3,
+}
+
+/// Applies `Teleport`s on the server and bumps the entity's `TeleportCount`.
+pub struct TeleportServerPlugin;
+
+impl Plugin for TeleportServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Teleport>()
+            .add_systems(Update, apply_teleports);
+    }
+}
+
+/// Makes remote entities snap when their `TeleportCount` changes.
+///
+/// Predicted entities need nothing: the teleport makes the rollback restart from the new
+/// `Transform`, and with no corrector registered for it, it is shown right away.
+pub struct TeleportClientPlugin;
+
+impl Plugin for TeleportClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            snap_interpolated_teleports.after(InterpolationSet::Interpolate),
+        );
+    }
+}
+
+pub(crate) fn apply_teleports(
+    tick_manager: Res<TickManager>,
+    mut teleports: EventReader<Teleport>,
+    mut entities: Query<(
+        &mut Position,
+        &mut Transform,
+        &mut LinearVelocity,
+        &mut TeleportCount,
+    )>,
+) {
+    for teleport in teleports.read() {
+        let Ok((mut position, mut transform, mut velocity, mut count)) =
+            entities.get_mut(teleport.entity)
+        else {
+            warn!("Cannot teleport {:?}, it has no position", teleport.entity);
+            continue;
+        };
+
+        info!("Teleporting {:?} to {:?}", teleport.entity, teleport.to);
+
+        position.0 = teleport.to;
+        transform.translation = teleport.to;
+        *velocity = LinearVelocity::ZERO;
+        count.count = count.count.wrapping_add(1);
+        count.tick = tick_manager.tick();
+    }
+}
+
+/// `TeleportCount` reaches interpolated entities right away, but their `Transform` runs behind.
+/// Once interpolation gets to the teleport, show the destination instead of the path to it.
+fn snap_interpolated_teleports(
+    mut players: Query<
+        (
+            &TeleportCount,
+            &InterpolateStatus<Transform>,
+            &mut Transform,
+        ),
+        With<Interpolated>,
+    >,
+) {
+    for (teleport, status, mut transform) in players.iter_mut() {
+        if teleport.count == 0 {
+            continue;
+        }
+        let (Some((start_tick, _)), Some((end_tick, end))) = (&status.start, &status.end) else {
+            continue;
+        };
+
+        if *start_tick < teleport.tick && teleport.tick <= *end_tick {
+            *transform = *end;
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::time::SystemTime;
+
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use lightyear::client::events::MessageEvent;
+use lightyear::prelude::client::{Rollback, RollbackState};
+use lightyear::prelude::*;
+
+use crate::protocol::{
+    Channel1, MovementSettings, MovementSettingsUpdate, ServerConnectionManager,
+};
+use crate::settings::{self, MOVEMENT_SETTINGS_FILE};
+
+/// How far ahead of the server's tick new settings apply. Clients predict ahead of the server,
+/// so this has to be more than their lead for them to switch on the same tick.
+const LEAD_TICKS: i16 = 64;
+/// Settings replaced longer ago than this are forgotten, no rollback goes that far back.
+const HISTORY_TICKS: i16 = 128;
+/// How often the server checks whether `MOVEMENT_SETTINGS_FILE` was edited.
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The `MovementSettings` in effect at each tick, so that a rollback across a change
+/// resimulates with the values the server used.
+#[derive(Resource, Debug)]
+pub struct MovementSettingsHistory {
+    /// Oldest first, never empty.
+    entries: Vec<(Tick, MovementSettings)>,
+}
+
+impl Default for MovementSettingsHistory {
+    fn default() -> Self {
+        Self::new(MovementSettings::default())
+    }
+}
+
+impl MovementSettingsHistory {
+    fn new(settings: MovementSettings) -> Self {
+        Self {
+            entries: vec![(Tick(0), settings)],
+        }
+    }
+
+    pub fn at(&self, tick: Tick) -> &MovementSettings {
+        let (_, settings) = self
+            .entries
+            .iter()
+            .rev()
+            .find(|(since, _)| *since <= tick)
+            .unwrap_or(&self.entries[0]);
+        settings
+    }
+
+    fn push(&mut self, since: Tick, settings: MovementSettings) {
+        // a later change the server made first is superseded
+        self.entries.retain(|(tick, _)| *tick < since);
+        self.entries.push((since, settings));
+    }
+
+    /// Drops the entries nothing can roll back to anymore. Ticks wrap around, so comparing
+    /// with very old ones would give the wrong answer.
+    fn forget_before(&mut self, tick: Tick) {
+        while self.entries.len() > 1 && self.entries[1].0 + HISTORY_TICKS <= tick {
+            self.entries.remove(0);
+        }
+    }
+}
+
+/// The tick being simulated, which is behind the `TickManager` during a rollback.
+pub(crate) fn simulated_tick(tick_manager: &TickManager, rollback: Option<&Rollback>) -> Tick {
+    match rollback.map(|rollback| &rollback.state) {
+        Some(RollbackState::ShouldRollback { current_tick }) => *current_tick,
+        _ => tick_manager.tick(),
+    }
+}
+
+/// Loads the `MovementSettings` from `MOVEMENT_SETTINGS_FILE`, reloads them when the file is
+/// edited, and sends them to clients.
+pub struct TuningServerPlugin;
+
+impl Plugin for TuningServerPlugin {
+    fn build(&self, app: &mut App) {
+        let movement_settings = settings::try_load::<MovementSettings>(MOVEMENT_SETTINGS_FILE)
+            .unwrap_or_else(|| {
+                // give designers something to edit
+                let defaults = MovementSettings::default();
+                settings::save(MOVEMENT_SETTINGS_FILE, &defaults);
+                defaults
+            });
+
+        app.insert_resource(MovementSettingsHistory::new(movement_settings.clone()))
+            .insert_resource(movement_settings)
+            .insert_resource(MovementSettingsFile {
+                poll: Timer::new(FILE_POLL_INTERVAL, TimerMode::Repeating),
+                modified: settings::modified(MOVEMENT_SETTINGS_FILE),
+            })
+            .add_systems(
+                Update,
+                (reload_movement_settings, broadcast_movement_settings).chain(),
+            )
+            .add_systems(FixedUpdate, forget_old_movement_settings);
+    }
+}
+
+/// Receives the `MovementSettings` from the server.
+pub struct TuningClientPlugin;
+
+impl Plugin for TuningClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MovementSettingsHistory>()
+            .add_systems(Update, receive_movement_settings)
+            .add_systems(FixedUpdate, forget_old_movement_settings);
+    }
+}
+
+#[derive(Resource)]
+struct MovementSettingsFile {
+    poll: Timer,
+    modified: Option<SystemTime>,
+}
+
+fn reload_movement_settings(
+    time: Res<Time>,
+    mut file: ResMut<MovementSettingsFile>,
+    mut settings: ResMut<MovementSettings>,
+) {
+    if !file.poll.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let modified = settings::modified(MOVEMENT_SETTINGS_FILE);
+    if modified == file.modified {
+        return;
+    }
+    file.modified = modified;
+
+    // a half-written or broken edit keeps the current settings
+    if let Some(reloaded) = settings::try_load::<MovementSettings>(MOVEMENT_SETTINGS_FILE) {
+        settings.set_if_neq(reloaded);
+    }
+}
+
+/// Applies changes to the `MovementSettings` a bit in the future, and tells every client when.
+fn broadcast_movement_settings(
+    tick_manager: Res<TickManager>,
+    settings: Res<MovementSettings>,
+    mut history: ResMut<MovementSettingsHistory>,
+    mut server: ResMut<ServerConnectionManager>,
+) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+
+    let since = tick_manager.tick() + LEAD_TICKS;
+    info!(
+        "Movement settings change at tick {:?}: {:?}",
+        since, *settings
+    );
+    history.push(since, settings.clone());
+
+    server
+        .send_message_to_target::<Channel1, MovementSettingsUpdate>(
+            MovementSettingsUpdate {
+                settings: settings.clone(),
+                since,
+            },
+            NetworkTarget::All,
+        )
+        .unwrap_or_else(|e| {
+            error!("Failed to send message: {:?}", e);
+        });
+}
+
+/// The updates a client accepted by the handshake starts from, oldest first: the settings in
+/// effect now, which apply right away since it has nothing to predict yet, then every change
+/// still pending.
+pub(crate) fn live_movement_settings(
+    tick_manager: &TickManager,
+    history: &MovementSettingsHistory,
+) -> Vec<MovementSettingsUpdate> {
+    let now = tick_manager.tick();
+    let current = MovementSettingsUpdate {
+        settings: history.at(now).clone(),
+        since: now,
+    };
+    let pending = history
+        .entries
+        .iter()
+        .filter(|(since, _)| now < *since)
+        .map(|(since, settings)| MovementSettingsUpdate {
+            settings: settings.clone(),
+            since: *since,
+        });
+    std::iter::once(current).chain(pending).collect()
+}
+
+fn receive_movement_settings(
+    mut updates: EventReader<MessageEvent<MovementSettingsUpdate>>,
+    mut history: ResMut<MovementSettingsHistory>,
+) {
+    for event in updates.read() {
+        let update = event.message();
+        info!(
+            "Movement settings change at tick {:?}: {:?}",
+            update.since, update.settings
+        );
+        history.push(update.since, update.settings.clone());
+    }
+}
+
+fn forget_old_movement_settings(
+    tick_manager: Res<TickManager>,
+    mut history: ResMut<MovementSettingsHistory>,
+) {
+    history.forget_before(tick_manager.tick());
+}
@@ -1,28 +1,115 @@
+use bevy::asset::{LoadedFolder, RecursiveDependencyLoadState};
 use bevy::{pbr::CascadeShadowConfigBuilder, prelude::*};
-use bevy_xpbd_3d::{components::RigidBody, plugins::collision::Collider};
+use lightyear::client::events::MessageEvent;
+use lightyear::prelude::client::*;
+use lightyear::prelude::*;
 
 use crate::client::GameClientState;
+use crate::level::{
+    spawn_level, LevelDescription, LevelGeometry, LevelReady, LevelSet, LEVELS_FOLDER,
+};
+use crate::loading::LoadingAssets;
+use crate::menu::DisconnectReason;
+use crate::protocol::{Channel1, ClientAssetsReady, ClientConnectionManager, LevelChunk};
+use crate::settings::ClientSettings;
+use crate::spectator::SpectatorMode;
+
+/// Receives the level from the server while `Connecting`, builds it and then tells the server
+/// we're ready to be spawned. This is the client's side of the handshake after `HandshakeAccept`.
+pub struct WorldPlugin;
+
+impl Plugin for WorldPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LevelDownload>()
+            .add_systems(
+                OnEnter(GameClientState::Connecting),
+                spawn_download_progress,
+            )
+            .add_systems(
+                OnExit(GameClientState::Connecting),
+                despawn_download_progress,
+            )
+            .add_systems(
+                PreUpdate,
+                (receive_level_chunks, build_downloaded_level)
+                    .chain()
+                    .after(MainSet::Receive)
+                    .run_if(in_state(GameClientState::Connecting)),
+            )
+            .add_systems(
+                Update,
+                (report_level_ready.after(LevelSet), update_download_progress)
+                    .run_if(in_state(GameClientState::Connecting)),
+            );
+    }
+}
+
+/// Everything spawned as part of the level, despawned when we go back to the main menu.
+#[derive(Component)]
+pub struct LevelEntity;
+
+/// The level chunks received so far. Cleared between connections by `cleanup_session`.
+#[derive(Resource, Default)]
+pub(crate) struct LevelDownload {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+}
+
+impl LevelDownload {
+    fn is_complete(&self) -> bool {
+        !self.chunks.is_empty() && self.received == self.chunks.len()
+    }
+}
+
+/// The downloaded level, spawned but not ready yet.
+#[derive(Resource)]
+struct PendingLevel {
+    root: Entity,
+    content_hash: u64,
+}
+
+#[derive(Component)]
+struct DownloadProgress;
+
+#[derive(Component)]
+struct DownloadProgressText;
+
+/// Assets shared by everything the client spawns, loaded during `GameClientState::Loading`.
+#[derive(Resource)]
+pub struct GameAssets {
+    pub player_mesh: Handle<Mesh>,
+    pub player_material: Handle<StandardMaterial>,
+    pub hat_mesh: Handle<Mesh>,
+    pub antenna_mesh: Handle<Mesh>,
+    /// Every level the client ships with, kept loaded so that building the one the server
+    /// picks doesn't wait on the disk.
+    pub levels: Handle<LoadedFolder>,
+}
 
 pub fn setup_world(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    mut next_state: ResMut<NextState<GameClientState>>,
+    asset_server: Res<AssetServer>,
+    mut loading: ResMut<LoadingAssets>,
 ) {
-    commands.spawn(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            shadows_enabled: true,
-            color: Color::hex("fffffb").unwrap(),
-            ..default()
-        },
-        transform: Transform {
-            translation: Vec3::new(0.0, 20.0, 0.0),
-            rotation: Quat::from_xyzw(-0.5, 0.0, 0.0, 1.0),
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadows_enabled: true,
+                color: Color::hex("fffffb").unwrap(),
+                ..default()
+            },
+            transform: Transform {
+                translation: Vec3::new(0.0, 20.0, 0.0),
+                rotation: Quat::from_xyzw(-0.5, 0.0, 0.0, 1.0),
+                ..default()
+            },
+            cascade_shadow_config: CascadeShadowConfigBuilder { ..default() }.into(),
             ..default()
         },
-        cascade_shadow_config: CascadeShadowConfigBuilder { ..default() }.into(),
-        ..default()
-    });
+        LevelEntity,
+    ));
     // .insert(Name::new("Sun"))
     // .insert(Sun);
 
@@ -31,18 +118,204 @@ pub fn setup_world(
         brightness: 0.1, // lower at night tho
     });
 
-    // Spawn the ground.
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Plane3d::default().mesh().size(128.0, 128.0)),
-            material: materials.add(Color::WHITE),
-            transform: Transform::from_xyz(0.0, 0.0, 0.0),
-            ..Default::default()
-        },
-        RigidBody::Static,
-        Collider::halfspace(Vec3::Y),
-    ));
+    let game_assets = GameAssets {
+        player_mesh: meshes.add(Cuboid::new(1.0, 1.0, 1.0)),
+        player_material: materials.add(Color::rgb(0.8, 0.7, 0.6)),
+        hat_mesh: meshes.add(Cuboid::new(0.7, 0.2, 0.7)),
+        antenna_mesh: meshes.add(Cuboid::new(0.08, 0.8, 0.08)),
+        levels: asset_server.load_folder(LEVELS_FOLDER),
+    };
+    // the meshes and material above exist as soon as they're added, the levels come from disk
+    loading.track("levels", &game_assets.levels);
+    commands.insert_resource(game_assets);
+
+    // The level itself comes from the server, `LoadingPlugin` moves on to connecting once
+    // everything above is ready
+}
+
+fn receive_level_chunks(
+    mut chunks: EventReader<MessageEvent<LevelChunk>>,
+    mut download: ResMut<LevelDownload>,
+) {
+    for event in chunks.read() {
+        let chunk = event.message();
+        if download.chunks.len() != chunk.total as usize {
+            download.chunks = vec![None; chunk.total as usize];
+            download.received = 0;
+        }
 
-    // Loaded now try to connect
-    next_state.set(GameClientState::Connecting);
+        let Some(slot) = download.chunks.get_mut(chunk.index as usize) else {
+            warn!("Ignoring out of range level chunk {}", chunk.index);
+            continue;
+        };
+        if slot.is_none() {
+            *slot = Some(chunk.bytes.clone());
+            download.received += 1;
+        }
+    }
+}
+
+fn build_downloaded_level(
+    mut download: ResMut<LevelDownload>,
+    old_level: Query<Entity, With<LevelGeometry>>,
+    asset_server: Res<AssetServer>,
+    mut reason: ResMut<DisconnectReason>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+    mut commands: Commands,
+) {
+    if !download.is_complete() {
+        return;
+    }
+
+    let bytes = std::mem::take(&mut *download)
+        .chunks
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect::<Vec<u8>>();
+    let level = match LevelDescription::from_bytes(&bytes) {
+        Ok(level) => level,
+        Err(e) => {
+            error!("Failed to read level from server: {}", e);
+            reason.0 = Some(format!("invalid level data from server: {}", e));
+            next_state.set(GameClientState::MainMenu);
+            return;
+        }
+    };
+    let content_hash = match level.content_hash() {
+        Ok(content_hash) => content_hash,
+        Err(e) => {
+            error!("Failed to read level {:?}: {}", level.name, e);
+            reason.0 = Some(format!(
+                "level {} is missing, is the game up to date?",
+                level.name
+            ));
+            next_state.set(GameClientState::MainMenu);
+            return;
+        }
+    };
+
+    // we may be reconnecting with the previous level still around
+    for entity in old_level.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let root = spawn_level(&mut commands, &asset_server, &level);
+    commands.insert_resource(level);
+    commands.insert_resource(PendingLevel { root, content_hash });
+}
+
+/// Waits for the downloaded level's scene to be spawned with its colliders.
+fn report_level_ready(
+    pending: Option<Res<PendingLevel>>,
+    mut ready: EventReader<LevelReady>,
+    scenes: Query<&Handle<Scene>>,
+    asset_server: Res<AssetServer>,
+    mut client: ResMut<ClientConnectionManager>,
+    settings: Res<ClientSettings>,
+    spectator: Res<SpectatorMode>,
+    mut reason: ResMut<DisconnectReason>,
+    mut next_state: ResMut<NextState<GameClientState>>,
+    mut commands: Commands,
+) {
+    let Some(pending) = pending else {
+        ready.clear();
+        return;
+    };
+
+    let failed = scenes.get(pending.root).is_ok_and(|scene| {
+        asset_server.get_recursive_dependency_load_state(scene)
+            == Some(RecursiveDependencyLoadState::Failed)
+    });
+    if failed {
+        error!("Failed to load the level scene");
+        reason.0 = Some("failed to load the level, is the game up to date?".to_string());
+        next_state.set(GameClientState::MainMenu);
+        commands.remove_resource::<PendingLevel>();
+        return;
+    }
+
+    if !ready.read().any(|ready| ready.0 == pending.root) {
+        return;
+    }
+    commands.remove_resource::<PendingLevel>();
+
+    // let server know we've finished loading assets
+    client
+        .send_message_to_target::<Channel1, ClientAssetsReady>(
+            ClientAssetsReady {
+                content_hash: pending.content_hash,
+                profile: settings.profile(),
+                spectator: spectator.0,
+            },
+            NetworkTarget::None,
+        )
+        .unwrap_or_else(|e| {
+            error!("Failed to send message: {:?}", e);
+        });
+
+    // players wait for their entity instead, see `wait_for_local_player_spawn`
+    if spectator.0 {
+        next_state.set(GameClientState::Playing);
+    }
+}
+
+fn spawn_download_progress(mut commands: Commands) {
+    commands.spawn((Camera2dBundle::default(), DownloadProgress));
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            DownloadProgress,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "Connecting...",
+                    TextStyle {
+                        font_size: 24.0,
+                        ..default()
+                    },
+                ),
+                DownloadProgressText,
+            ));
+        });
+}
+
+fn update_download_progress(
+    download: Res<LevelDownload>,
+    pending: Option<Res<PendingLevel>>,
+    mut text: Query<&mut Text, With<DownloadProgressText>>,
+) {
+    let progress = if pending.is_some() {
+        "Building level...".to_string()
+    } else if !download.chunks.is_empty() {
+        format!(
+            "Downloading level {}/{}",
+            download.received,
+            download.chunks.len()
+        )
+    } else {
+        return;
+    };
+    for mut text in text.iter_mut() {
+        text.sections[0].value = progress.clone();
+    }
+}
+
+fn despawn_download_progress(
+    mut commands: Commands,
+    progress: Query<Entity, With<DownloadProgress>>,
+) {
+    for entity in progress.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
 }